@@ -0,0 +1,100 @@
+// Route dispatch micro-benchmark: segment-trie Router vs the old linear scan
+//
+//   cargo run --release --example router_benchmark
+use hyper::Method;
+use rust_flask::router::Router;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ROUTE_COUNTS: [usize; 4] = [10, 100, 1_000, 5_000];
+const TRIE_LOOKUPS: usize = 200_000;
+// The scan gets slower with every route, so give it a fixed budget of pattern comparisons
+const SCAN_COMPARISONS: usize = 20_000_000;
+
+// Each resource contributes one static and two parameterised routes
+fn patterns(resources: usize) -> Vec<String> {
+    let mut patterns = Vec::with_capacity(resources * 3);
+    for i in 0..resources {
+        patterns.push(format!("/api/resource{}/list", i));
+        patterns.push(format!("/api/resource{}/{{id}}", i));
+        patterns.push(format!("/api/resource{}/{{id}}/items/{{item_id}}", i));
+    }
+    patterns
+}
+
+// Request paths that hit the last-registered resources, the worst case for a scan
+fn request_paths(resources: usize) -> Vec<String> {
+    let last = resources - 1;
+    vec![
+        format!("/api/resource{}/list", last),
+        format!("/api/resource{}/42", last),
+        format!("/api/resource{}/42/items/7", last),
+        "/api/missing/route".to_string(),
+    ]
+}
+
+// The pre-router algorithm: compare every pattern segment by segment
+fn linear_match(patterns: &[String], path: &str) -> Option<usize> {
+    let path_parts: Vec<&str> = path.split('/').collect();
+    'patterns: for (index, pattern) in patterns.iter().enumerate() {
+        let pattern_parts: Vec<&str> = pattern.split('/').collect();
+        if pattern_parts.len() != path_parts.len() {
+            continue;
+        }
+        for (pattern_part, path_part) in pattern_parts.iter().zip(&path_parts) {
+            let is_param = pattern_part.starts_with('{') && pattern_part.ends_with('}');
+            if !is_param && pattern_part != path_part {
+                continue 'patterns;
+            }
+        }
+        return Some(index);
+    }
+    None
+}
+
+fn time_per_lookup(lookups: usize, mut lookup: impl FnMut(&str), paths: &[String]) -> Duration {
+    let start = Instant::now();
+    for i in 0..lookups {
+        lookup(&paths[i % paths.len()]);
+    }
+    start.elapsed() / lookups as u32
+}
+
+fn main() {
+    println!("🦀 RustFlask router dispatch benchmark (mean time per lookup)");
+    println!();
+    println!("{:>8} {:>14} {:>14} {:>10}", "routes", "trie", "linear scan", "speedup");
+
+    for resources in ROUTE_COUNTS {
+        let patterns = patterns(resources);
+        let paths = request_paths(resources);
+
+        let mut router = Router::new();
+        for (index, pattern) in patterns.iter().enumerate() {
+            router.insert(Some(Method::GET), pattern, index).unwrap();
+        }
+
+        let trie = time_per_lookup(
+            TRIE_LOOKUPS,
+            |path| {
+                black_box(router.at(&Method::GET, path).map(|m| *m.value));
+            },
+            &paths,
+        );
+        let linear = time_per_lookup(
+            (SCAN_COMPARISONS / patterns.len()).max(paths.len()),
+            |path| {
+                black_box(linear_match(&patterns, path));
+            },
+            &paths,
+        );
+
+        println!(
+            "{:>8} {:>14?} {:>14?} {:>9.1}x",
+            patterns.len(),
+            trie,
+            linear,
+            linear.as_secs_f64() / trie.as_secs_f64()
+        );
+    }
+}
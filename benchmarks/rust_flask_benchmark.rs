@@ -1,7 +1,6 @@
 // RustFlask benchmark application to compare against Flask
-use rust_flask::{FlaskApp, FlaskConfig, text_response, json_response};
+use rust_flask::{FlaskApp, FlaskConfig, json_response};
 use hyper::{Response, Body};
-use serde_json;
 
 #[tokio::main]
 async fn main() {
@@ -9,6 +8,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8086,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
@@ -164,7 +164,7 @@ async fn main() {
     println!("👑 Running on: http://127.0.0.1:8086/");
     println!("📊 Benchmark ready - compare against Python Flask on port 8000");
     println!("🔧 All endpoints mirror Flask benchmark server");
-    println!("");
+    println!();
     println!("💡 Endpoints to test:");
     println!("   • /hello           - Simple greeting");
     println!("   • /hello/World     - Personalized greeting");  
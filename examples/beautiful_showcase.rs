@@ -1,6 +1,5 @@
 use rust_flask::{FlaskApp, FlaskConfig, text_response, json_response};
-use hyper::{Method, Response, Body};
-use std::collections::HashMap;
+use hyper::{Response, Body};
 
 #[tokio::main]
 async fn main() {
@@ -8,6 +7,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8083,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
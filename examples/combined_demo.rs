@@ -1,6 +1,5 @@
 use rust_flask::{FlaskApp, FlaskConfig, text_response, json_response};
 use hyper::{Response, Body};
-use serde_json;
 
 #[tokio::main]
 async fn main() {
@@ -8,6 +7,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8085,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
@@ -544,19 +544,19 @@ Your POST request was processed correctly!")
     println!("📍 Port: {}", config.port);
     println!("🐛 Debug mode: {}", config.debug);
     println!("🌍 UTF-8 encoding: ✅ ENABLED");
-    println!("");
+    println!();
     println!("📚 Try these URLs:");
     println!("   http://localhost:{}/            - Homepage", config.port);
     println!("   http://localhost:{}/utf8        - Unicode test", config.port);
     println!("   http://localhost:{}/hello/World - Personalized", config.port);
     println!("   http://localhost:{}/json        - JSON demo", config.port);
     println!("   http://localhost:{}/status      - Server status", config.port);
-    println!("");
+    println!();
     println!("💻 Command line:");
     println!("   curl http://localhost:{}/hello/世界", config.port);
     println!("   curl -X POST http://localhost:{}/echo", config.port);
     println!("   curl http://localhost:{}/users/rust/posts/tutorial", config.port);
-    println!("");
+    println!();
 
     app.run([127, 0, 0, 1], config.port).await;
 }
\ No newline at end of file
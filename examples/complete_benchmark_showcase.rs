@@ -1,7 +1,6 @@
 // Complete benchmark showcase with full magazine-style report integration
 use rust_flask::{FlaskApp, FlaskConfig, json_response};
 use hyper::{Response, Body};
-use serde_json;
 
 #[tokio::main]
 async fn main() {
@@ -9,6 +8,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8086,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
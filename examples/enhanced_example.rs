@@ -1,5 +1,5 @@
-use rust_flask::{FlaskApp, FlaskConfig, text_response, json_response, Json};
-use hyper::{Method, Response, Body, StatusCode};
+use rust_flask::{handler, FlaskApp, FlaskConfig, Json, Path, Query, text_response};
+use hyper::StatusCode;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -8,12 +8,27 @@ struct User {
     email: String,
 }
 
+#[derive(Deserialize)]
+struct Search {
+    q: String,
+}
+
+// Extractor arguments: a malformed query string is rejected with 400 before this runs
+async fn search_users(Query(search): Query<Search>) -> String {
+    format!("Searching users for '{}'", search.q)
+}
+
+async fn user_post(Path((user_id, post_id)): Path<(u64, u64)>) -> String {
+    format!("User {} has post {}", user_id, post_id)
+}
+
 #[tokio::main]
 async fn main() {
     let config = FlaskConfig {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8080,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config);
@@ -23,18 +38,18 @@ async fn main() {
         text_response("Hello from GET method!")
     }).await;
 
-    app.post("/users", |_req, _params| {
-        // For now, return a simple response - async JSON parsing would require
-        // more complex architecture with async trait support
-        text_response("User creation endpoint (JSON parsing coming soon!)")
+    // Async handler: await the request body before responding
+    app.post("/users", |mut req, _params| async move {
+        match Json::<User>::from_request(&mut req).await {
+            Ok(Json(user)) => Ok((StatusCode::CREATED, format!("Created user {} <{}>", user.name, user.email))),
+            Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+        }
     }).await;
 
-    // URL parameters
-    app.get("/users/{id}/posts/{post_id}", |_req, params| {
-        let user_id = params.params.get("id").map(|s| s.as_str()).unwrap_or("unknown");
-        let post_id = params.params.get("post_id").map(|s| s.as_str()).unwrap_or("unknown");
-        text_response(&format!("User {} has post {}", user_id, post_id))
-    }).await;
+    app.get("/users/search", handler(search_users)).await;
+
+    // URL parameters, parsed into typed values (a non-numeric id answers 400)
+    app.get("/users/{id}/posts/{post_id}", handler(user_post)).await;
 
     // Configuration access
     let debug_mode = app.config().debug;
@@ -1,5 +1,5 @@
 use rust_flask::{FlaskApp, FlaskConfig, text_response, json_response};
-use hyper::{Method, Response, Body};
+use hyper::{Response, Body};
 use std::collections::HashMap;
 
 #[tokio::main]
@@ -8,6 +8,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8082,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
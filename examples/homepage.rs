@@ -1,7 +1,6 @@
 // RustFlask combined benchmark showcase with interactive section
-use rust_flask::{FlaskApp, FlaskConfig, text_response, json_response};
-use hyper::{Response, Body, HeaderMap};
-use serde_json;
+use rust_flask::{FlaskApp, FlaskConfig, json_response};
+use hyper::{Response, Body};
 
 #[tokio::main]
 async fn main() {
@@ -9,6 +8,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8086,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
@@ -125,8 +125,9 @@ async fn main() {
     app.run([127, 0, 0, 1], config.port).await;
 }
 
+#[allow(clippy::useless_format)]
 fn generate_combined_homepage() -> String {
-    format!(r#"<!DOCTYPE html>
+    format!(r##"<!DOCTYPE html>
 <html lang="en">
 <head>
     <meta charset="UTF-8">
@@ -708,5 +709,5 @@ fn generate_combined_homepage() -> String {
 </body>
 </html>
 
-"#))
+"##)
 }
\ No newline at end of file
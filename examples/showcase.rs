@@ -1,8 +1,8 @@
 use rust_flask::{FlaskApp, FlaskConfig, text_response, json_response};
-use hyper::Method;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
+#[allow(dead_code)]
 struct User {
     name: String,
     email: String,
@@ -14,6 +14,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8081,  // Changed port to avoid conflict
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
@@ -143,7 +144,7 @@ Try them out with curl or your browser!
             "#,
             path
         );
-        text_response(&not_found.trim())
+        text_response(not_found.trim())
     }).await;
 
     // Print startup info
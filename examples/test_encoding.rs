@@ -7,6 +7,7 @@ async fn main() {
         debug: true,
         host: "127.0.0.1".to_string(),
         port: 8001,
+        ..FlaskConfig::default()
     };
 
     let app = FlaskApp::with_config(config.clone());
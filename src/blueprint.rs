@@ -0,0 +1,406 @@
+// Blueprints group routes (and nested blueprints) so they can be defined in
+// one module and mounted under a URL prefix with `FlaskApp::register_blueprint`.
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use tower::{BoxError, Layer, Service};
+
+use crate::errorhandlers::{self, ErrorHandlers};
+use crate::handler::{self, HandlerOutput, RouteHandler};
+use crate::hooks::{self, Hooks};
+use crate::middleware::{self, BoxedService, Middleware, RouteWrap};
+use crate::rule::Rule;
+use crate::static_files::send_from_directory;
+use crate::{IntoResponse, RouteParams};
+
+pub struct Blueprint {
+    name: String,
+    routes: Vec<BlueprintRoute>,
+    hooks: Hooks,
+    error_handlers: ErrorHandlers,
+    // Applied to every route of this blueprint and its children when mounted
+    wraps: Vec<RouteWrap>,
+    blueprints: Vec<(Blueprint, String)>,
+}
+
+struct BlueprintRoute {
+    method: Option<Method>,
+    rule: Rule,
+    handler: RouteHandler,
+}
+
+// A blueprint route resolved against its mount point
+pub(crate) struct MountedRoute {
+    pub(crate) method: Option<Method>,
+    pub(crate) path: String,
+    // "<dotted blueprint name>.<endpoint>"
+    pub(crate) endpoint: Option<String>,
+    pub(crate) blueprint: String,
+    pub(crate) max_content_length: Option<Option<u64>>,
+    pub(crate) strict_slashes: Option<bool>,
+    pub(crate) handler: RouteHandler,
+}
+
+// Everything `mount` collects from a blueprint tree
+#[derive(Default)]
+pub(crate) struct Mounted {
+    pub(crate) routes: Vec<MountedRoute>,
+    // Dotted names of every blueprint in the tree
+    pub(crate) names: Vec<String>,
+    pub(crate) hooks: Hooks,
+    pub(crate) error_handlers: ErrorHandlers,
+}
+
+impl Blueprint {
+    // Names are joined with '.' when nesting, so they may not contain one
+    pub fn new(name: &str) -> Self {
+        assert!(!name.is_empty(), "Blueprint name may not be empty");
+        assert!(!name.contains('.'), "Blueprint name {:?} may not contain a dot", name);
+        Blueprint {
+            name: name.to_string(),
+            routes: Vec::new(),
+            hooks: Hooks::default(),
+            error_handlers: ErrorHandlers::default(),
+            wraps: Vec::new(),
+            blueprints: Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn route<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(None, rule.into(), handler::boxed(handler));
+    }
+
+    pub fn get<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::GET), rule.into(), handler::boxed(handler));
+    }
+
+    pub fn post<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::POST), rule.into(), handler::boxed(handler));
+    }
+
+    pub fn put<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::PUT), rule.into(), handler::boxed(handler));
+    }
+
+    pub fn delete<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::DELETE), rule.into(), handler::boxed(handler));
+    }
+
+    pub fn patch<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::PATCH), rule.into(), handler::boxed(handler));
+    }
+
+    pub fn head<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::HEAD), rule.into(), handler::boxed(handler));
+    }
+
+    pub fn options<F, R, M>(&mut self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::OPTIONS), rule.into(), handler::boxed(handler));
+    }
+
+    pub fn route_methods<F, R, M>(&mut self, methods: &[Method], rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        let rule = rule.into();
+        let handler = handler::boxed(handler);
+        for method in methods {
+            self.add_route(Some(method.clone()), rule.clone(), Arc::clone(&handler));
+        }
+    }
+
+    // Serves files from `directory` at `<url_path>/<filename>` below the
+    // blueprint's prefix, e.g. `bp.static_folder("assets", "/static")`
+    pub fn static_folder(&mut self, directory: impl Into<PathBuf>, url_path: &str) {
+        let directory = Arc::new(directory.into());
+        let path = format!("{}/{{filename:path}}", url_path.trim_end_matches('/'));
+        self.get(Rule::new(&path).endpoint("static"), move |_req, params| {
+            let directory = Arc::clone(&directory);
+            async move {
+                let filename = params.get("filename").unwrap_or_default();
+                send_from_directory(directory.as_path(), filename).await
+            }
+        });
+    }
+
+    // Hooks that only run for requests routed to this blueprint (or one
+    // nested in it); see `FlaskApp::before_request`
+    pub fn before_request<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(Request<Body>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Request<Body>, Response<Body>>> + Send + 'static,
+    {
+        self.hooks.add_before(hooks::before(hook));
+    }
+
+    pub fn after_request<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(Request<()>, Response<Body>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.hooks.add_after(hooks::after(hook));
+    }
+
+    pub fn teardown_request<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(Request<()>, Option<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.add_teardown(hooks::teardown(hook));
+    }
+
+    // Error handlers for requests routed to this blueprint; they take
+    // precedence over the app's. See `FlaskApp::errorhandler`.
+    pub fn errorhandler<F, Fut>(&mut self, status: StatusCode, handler: F)
+    where
+        F: Fn(Request<()>, Response<Body>) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.error_handlers.add_status(status, errorhandlers::status(handler));
+    }
+
+    pub fn errorhandler_for<E, F, R>(&mut self, handler: F)
+    where
+        E: 'static,
+        F: Fn(E) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.error_handlers.add_type(errorhandlers::typed(handler));
+    }
+
+    // Middleware and layers around every route of this blueprint, including
+    // nested blueprints' and routes registered earlier. Like `Rule::middleware`
+    // they run inside the app's middleware and hooks; a parent's wrap its
+    // children's, which wrap the route's own.
+    pub fn middleware<M: Middleware>(&mut self, middleware: M) {
+        self.wraps.push(middleware::route_middleware(middleware));
+    }
+
+    pub fn layer<L>(&mut self, layer: L)
+    where
+        L: Layer<BoxedService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+    {
+        self.wraps.push(middleware::route_layer(middleware::erase_layer(layer)));
+    }
+
+    // Mounts `blueprint` at `url_prefix` relative to this blueprint; its name
+    // becomes "<this name>.<child name>"
+    pub fn register_blueprint(&mut self, blueprint: Blueprint, url_prefix: &str) {
+        self.blueprints.push((blueprint, url_prefix.to_string()));
+    }
+
+    fn add_route(&mut self, method: Option<Method>, rule: Rule, handler: RouteHandler) {
+        let handler = middleware::wrap_route(&rule.wraps, handler);
+        self.routes.push(BlueprintRoute { method, rule, handler });
+    }
+
+    // Flattens this blueprint and its children into routes with full paths
+    // and hooks scoped to their dotted blueprint names. `parent_wraps` are the
+    // middleware of the enclosing blueprints, outermost first.
+    pub(crate) fn mount(
+        self,
+        url_prefix: &str,
+        parent: Option<&str>,
+        parent_wraps: &[RouteWrap],
+        mounted: &mut Mounted,
+    ) {
+        let name = match parent {
+            Some(parent) => format!("{}.{}", parent, self.name),
+            None => self.name,
+        };
+        let wraps: Vec<RouteWrap> = parent_wraps.iter().chain(&self.wraps).cloned().collect();
+        mounted.names.push(name.clone());
+        mounted.hooks.extend_scoped(self.hooks, &name);
+        mounted.error_handlers.extend_scoped(self.error_handlers, &name);
+
+        for route in self.routes {
+            mounted.routes.push(MountedRoute {
+                method: route.method,
+                path: join_paths(url_prefix, &route.rule.path),
+                endpoint: route.rule.endpoint.map(|endpoint| format!("{}.{}", name, endpoint)),
+                blueprint: name.clone(),
+                max_content_length: route.rule.max_content_length,
+                strict_slashes: route.rule.strict_slashes,
+                handler: middleware::wrap_route(&wraps, route.handler),
+            });
+        }
+
+        for (child, child_prefix) in self.blueprints {
+            child.mount(&join_paths(url_prefix, &child_prefix), Some(&name), &wraps, mounted);
+        }
+    }
+}
+
+// "/api/" + "/users" -> "/api/users"; a "/" route keeps the slash as in Flask
+fn join_paths(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    if path.is_empty() {
+        return if prefix.is_empty() { "/".to_string() } else { prefix.to_string() };
+    }
+    if path.starts_with('/') {
+        format!("{}{}", prefix, path)
+    } else {
+        format!("{}/{}", prefix, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::get;
+    use crate::{from_fn, FlaskApp, MatchedRoute, Next};
+
+    #[test]
+    fn test_join_paths() {
+        assert_eq!(join_paths("/api/v1", "/users"), "/api/v1/users");
+        assert_eq!(join_paths("/api/v1/", "/users"), "/api/v1/users");
+        assert_eq!(join_paths("/api", "/"), "/api/");
+        assert_eq!(join_paths("/api", ""), "/api");
+        assert_eq!(join_paths("", "/users"), "/users");
+        assert_eq!(join_paths("/", "users"), "/users");
+    }
+
+    #[test]
+    fn test_nested_mount_names_and_prefixes() {
+        let mut users = Blueprint::new("users");
+        users.get(Rule::new("/{id}").endpoint("show"), |_req, _params| "user");
+
+        let mut api = Blueprint::new("api");
+        api.get("/health", |_req, _params| "ok");
+        api.register_blueprint(users, "/users");
+
+        let mut mounted = Mounted::default();
+        api.mount("/api/v1", None, &[], &mut mounted);
+        assert_eq!(mounted.names, vec!["api", "api.users"]);
+
+        let routes = mounted.routes;
+        let mounted: Vec<(&str, &str)> = routes
+            .iter()
+            .map(|r| (r.path.as_str(), r.blueprint.as_str()))
+            .collect();
+        assert_eq!(
+            mounted,
+            vec![("/api/v1/health", "api"), ("/api/v1/users/{id}", "api.users")]
+        );
+        assert_eq!(routes[0].endpoint, None);
+        assert_eq!(routes[1].endpoint.as_deref(), Some("api.users.show"));
+    }
+
+    #[tokio::test]
+    async fn test_register_blueprint() {
+        let mut users = Blueprint::new("users");
+        users.get("/{id}", |req, params| {
+            let matched = req.extensions().get::<MatchedRoute>().cloned().unwrap_or_default();
+            format!(
+                "{} {} {}",
+                params.get("id").unwrap_or_default(),
+                matched.pattern,
+                matched.blueprint.unwrap_or_default()
+            )
+        });
+
+        let mut api = Blueprint::new("api");
+        api.static_folder(".", "/static");
+        api.register_blueprint(users, "/users");
+
+        let app = FlaskApp::new();
+        app.register_blueprint(api, "/api/v1").await;
+
+        assert_eq!(get(&app, "/api/v1/users/5").await.body, "5 /api/v1/users/{id} api.users");
+        assert_eq!(get(&app, "/api/v1/static/Cargo.toml").await.status, StatusCode::OK);
+        assert_eq!(get(&app, "/api/v1/static/../Cargo.toml").await.status, StatusCode::NOT_FOUND);
+        assert_eq!(get(&app, "/users/5").await.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_blueprint_middleware() {
+        let tag = |name: &'static str| {
+            from_fn(move |req, next: Next| async move {
+                let mut response = next.run(req).await;
+                let trail = match response.headers().get("x-trail") {
+                    Some(trail) => format!("{},{}", trail.to_str().unwrap(), name),
+                    None => name.to_string(),
+                };
+                response.headers_mut().insert("x-trail", trail.parse().unwrap());
+                response
+            })
+        };
+
+        let mut users = Blueprint::new("users");
+        users.get(Rule::new("/{id}").middleware(tag("route")), |_req, _params| "user");
+        users.get("/slow", |_req, _params| async {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            "too late"
+        });
+        users.middleware(tag("users"));
+        users.layer(tower::timeout::TimeoutLayer::new(std::time::Duration::from_millis(20)));
+
+        let mut api = Blueprint::new("api");
+        api.get("/health", |_req, _params| "ok");
+        api.register_blueprint(users, "/users");
+        api.middleware(tag("api"));
+
+        let app = FlaskApp::new();
+        app.get("/", |_req, _params| "home").await;
+        app.register_blueprint(api, "/api").await;
+
+        assert_eq!(get(&app, "/api/users/1").await.header("x-trail"), Some("route,users,api"));
+        assert_eq!(get(&app, "/api/health").await.header("x-trail"), Some("api"));
+        assert_eq!(get(&app, "/").await.header("x-trail"), None);
+
+        let response = get(&app, "/api/users/slow").await;
+        assert_eq!(response.status, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(response.header("x-trail"), Some("users,api"));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "already registered")]
+    async fn test_duplicate_blueprint_name_panics() {
+        let app = FlaskApp::new();
+        app.register_blueprint(Blueprint::new("api"), "/v1").await;
+        app.register_blueprint(Blueprint::new("api"), "/v2").await;
+    }
+}
@@ -0,0 +1,324 @@
+// Request body size limits and incremental body access.
+//
+// `FlaskConfig::max_content_length` (Flask's MAX_CONTENT_LENGTH) applies to
+// every request from the start, so app middleware reading the body is held
+// to it too. Once the request is routed, `Rule::max_content_length` or
+// `Rule::unlimited_body` replaces it for the rest of the request. A request
+// whose Content-Length is over the route's limit is answered with 413 before
+// the handler runs. Otherwise reading past the limit fails, and the body
+// extractors (`Json`, `Form`, `Multipart`, `BodyStream`) turn that failure
+// into 413 as well.
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::CONTENT_LENGTH;
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::extract::FromRequest;
+use crate::IntoResponse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthLimitError {
+    limit: u64,
+}
+
+impl LengthLimitError {
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+impl fmt::Display for LengthLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request body exceeds the limit of {} bytes", self.limit)
+    }
+}
+
+impl StdError for LengthLimitError {}
+
+// The limit of a body wrapped by `limit_body`, kept in the request's
+// extensions so it can be changed after routing; u64::MAX is unlimited
+#[derive(Clone)]
+struct SharedLimit(Arc<AtomicU64>);
+
+// Sets the limit on how much of `req`'s body can be read, wrapping the body
+// the first time a limit is set
+pub(crate) fn limit_body(req: &mut Request<Body>, limit: Option<u64>) {
+    let limit = limit.unwrap_or(u64::MAX);
+    if let Some(shared) = req.extensions().get::<SharedLimit>() {
+        shared.0.store(limit, Ordering::Relaxed);
+        return;
+    }
+    if limit == u64::MAX {
+        return;
+    }
+
+    let shared = Arc::new(AtomicU64::new(limit));
+    req.extensions_mut().insert(SharedLimit(Arc::clone(&shared)));
+    let body = std::mem::take(req.body_mut());
+    let stream = futures_util::stream::unfold(Some((body, 0u64)), move |state| {
+        let shared = Arc::clone(&shared);
+        async move {
+            let (mut body, read) = state?;
+            match body.data().await? {
+                Ok(chunk) => {
+                    let read = read + chunk.len() as u64;
+                    let limit = shared.load(Ordering::Relaxed);
+                    if read > limit {
+                        let error: Box<dyn StdError + Send + Sync> = Box::new(LengthLimitError { limit });
+                        Some((Err(error), None))
+                    } else {
+                        Some((Ok(chunk), Some((body, read))))
+                    }
+                }
+                Err(e) => Some((Err(e.into()), None)),
+            }
+        }
+    });
+    *req.body_mut() = Body::wrap_stream(stream);
+}
+
+// Err when the declared Content-Length is already over `limit`
+pub(crate) fn check_length(req: &Request<Body>, limit: Option<u64>) -> Result<(), LengthLimitError> {
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    match declared {
+        Some(length) if length > limit => Err(LengthLimitError { limit }),
+        _ => Ok(()),
+    }
+}
+
+// Finds the limit error behind a failed body read
+pub(crate) fn limit_exceeded(error: &(dyn StdError + 'static)) -> Option<LengthLimitError> {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(limit) = error.downcast_ref::<LengthLimitError>() {
+            return Some(*limit);
+        }
+        current = error.source();
+    }
+    None
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    TooLarge(LengthLimitError),
+    Read(hyper::Error),
+}
+
+impl BodyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            BodyError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            BodyError::Read(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<hyper::Error> for BodyError {
+    fn from(error: hyper::Error) -> Self {
+        match limit_exceeded(&error) {
+            Some(limit) => BodyError::TooLarge(limit),
+            None => BodyError::Read(error),
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "{}", limit),
+            BodyError::Read(e) => write!(f, "Failed to read body: {}", e),
+        }
+    }
+}
+
+impl StdError for BodyError {}
+
+impl IntoResponse for BodyError {
+    fn into_response(self) -> Response<Body> {
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+// Reads the whole body, honouring the route's limit
+pub(crate) async fn to_bytes(body: Body) -> Result<Bytes, BodyError> {
+    Ok(hyper::body::to_bytes(body).await?)
+}
+
+// The request body as it arrives, for handlers that process large uploads
+// chunk by chunk:
+//
+//   async fn ingest(mut body: BodyStream) -> Result<String, BodyError> {
+//       let mut total = 0;
+//       while let Some(chunk) = body.chunk().await? {
+//           total += chunk.len();
+//       }
+//       Ok(format!("{} bytes", total))
+//   }
+#[derive(Debug)]
+pub struct BodyStream {
+    body: Body,
+}
+
+impl BodyStream {
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, BodyError> {
+        match self.body.data().await {
+            Some(chunk) => Ok(Some(chunk?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+}
+
+impl FromRequest for BodyStream {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request(req: Request<Body>) -> Result<Self, Self::Rejection> {
+        Ok(BodyStream {
+            body: req.into_body(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::CONTENT_TYPE;
+    use hyper::Method;
+    use crate::testing::send;
+    use crate::{handler, FlaskApp, FlaskConfig, Json, Next, Rule};
+
+    fn chunked(chunks: &'static [&'static str]) -> Request<Body> {
+        let stream = futures_util::stream::iter(chunks.iter().map(|c| Ok::<_, std::io::Error>(*c)));
+        Request::new(Body::wrap_stream(stream))
+    }
+
+    #[tokio::test]
+    async fn test_declared_length_over_limit() {
+        let req = Request::builder()
+            .header(CONTENT_LENGTH, "11")
+            .body(Body::from("hello world"))
+            .unwrap();
+        assert_eq!(check_length(&req, Some(10)), Err(LengthLimitError { limit: 10 }));
+        assert_eq!(check_length(&req, None), Ok(()));
+
+        let mut req = Request::new(Body::from("hello"));
+        limit_body(&mut req, Some(10));
+        assert_eq!(&to_bytes(req.into_body()).await.unwrap()[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_stops_at_limit() {
+        let mut req = chunked(&["abcd", "efgh", "ijkl"]);
+        limit_body(&mut req, Some(10));
+
+        let mut body = BodyStream::from_request(req).await.unwrap();
+        assert_eq!(body.chunk().await.unwrap().as_deref(), Some(&b"abcd"[..]));
+        assert_eq!(body.chunk().await.unwrap().as_deref(), Some(&b"efgh"[..]));
+        let err = body.chunk().await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_limit_can_be_changed_after_wrapping() {
+        let mut req = chunked(&["abcd", "efgh", "ijkl"]);
+        limit_body(&mut req, Some(4));
+        limit_body(&mut req, None);
+        assert_eq!(&to_bytes(req.into_body()).await.unwrap()[..], b"abcdefghijkl");
+
+        let mut req = chunked(&["abcd", "efgh"]);
+        limit_body(&mut req, Some(100));
+        limit_body(&mut req, Some(6));
+        let err = to_bytes(req.into_body()).await.unwrap_err();
+        assert!(matches!(err, BodyError::TooLarge(limit) if limit.limit() == 6), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_max_content_length() {
+        // Buffers the body when asked to, as a signature check would
+        async fn audit(req: Request<Body>, next: Next) -> Response<Body> {
+            if !req.headers().contains_key("x-audit") {
+                return next.run(req).await;
+            }
+            let (parts, body) = req.into_parts();
+            match hyper::body::to_bytes(body).await {
+                Ok(bytes) => next.run(Request::from_parts(parts, Body::from(bytes))).await,
+                Err(e) => BodyError::from(e).into_response(),
+            }
+        }
+
+        let app = FlaskApp::with_config(FlaskConfig {
+            max_content_length: Some(16),
+            ..FlaskConfig::default()
+        })
+        .middleware(audit);
+        app.post(Rule::new("/any").unlimited_body(), handler(|body: BodyStream| async move {
+            Ok::<_, BodyError>(to_bytes(body.into_body()).await?.len().to_string())
+        }))
+        .await;
+        app.post("/echo", handler(|Json(value): Json<serde_json::Value>| async move { value.to_string() }))
+            .await;
+        app.post(Rule::new("/upload").max_content_length(1024), handler(|mut body: BodyStream| async move {
+            let mut total = 0;
+            while let Some(chunk) = body.chunk().await? {
+                total += chunk.len();
+            }
+            Ok::<_, BodyError>(total.to_string())
+        }))
+        .await;
+
+        let post = |uri: &str, body: Body| {
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .unwrap();
+            send(&app, req)
+        };
+        let json_chunks = |n: usize| Body::wrap_stream(futures_util::stream::iter(
+            (0..n).map(|_| Ok::<_, std::io::Error>("[1,2,3,4]")),
+        ));
+
+        // Declared lengths are checked before the handler runs
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/echo")
+            .header(CONTENT_LENGTH, "1000")
+            .body(Body::from("[]"))
+            .unwrap();
+        assert_eq!(send(&app, req).await.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(post("/echo", Body::from("[1,2,3]")).await.status, StatusCode::OK);
+
+        // Bodies without a Content-Length are cut off while streaming
+        assert_eq!(post("/echo", json_chunks(1)).await.status, StatusCode::OK);
+        assert_eq!(post("/echo", json_chunks(2)).await.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // The per-route limit replaces the global one
+        assert_eq!(post("/upload", json_chunks(10)).await.body, "90");
+        assert_eq!(post("/upload", json_chunks(200)).await.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(post("/any", json_chunks(200)).await.body, "1800");
+
+        // App middleware runs before routing and is held to the app's limit
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/upload")
+            .header("x-audit", "1")
+            .body(json_chunks(10))
+            .unwrap();
+        assert_eq!(send(&app, req).await.status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,196 @@
+// Per-request context, Flask's `g`.
+//
+// Every request gets a fresh `G` in its extensions before any hook runs; a
+// request that did not come through the app (e.g. one built in a test) gets
+// one on first access.
+// Clones share the same storage, so a value stored by a hook or extractor is
+// visible to the handler and to the hooks that run after it:
+//
+//   req.g().insert(CurrentUser { id: 7 });
+//   let user = req.g().get::<CurrentUser>();
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use hyper::http::request::Parts;
+use hyper::http::Extensions;
+use hyper::Request;
+
+use crate::extract::FromRequestParts;
+use crate::query::Args;
+use crate::state::StateMap;
+
+type AnyMap = HashMap<TypeId, Box<dyn Any + Send + Sync>>;
+
+#[derive(Clone, Default)]
+pub struct G {
+    values: Arc<Mutex<AnyMap>>,
+}
+
+impl G {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Stores `value`, returning the previous value of the same type
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.lock()
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.lock().get(&TypeId::of::<T>())?.downcast_ref::<T>().cloned()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&self) -> Option<T> {
+        self.lock()
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok().map(|value| *value))
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.lock().contains_key(&TypeId::of::<T>())
+    }
+
+    // Runs `f` on the stored value in place, e.g. to bump a counter
+    pub fn with_mut<T: Send + Sync + 'static, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.lock().get_mut(&TypeId::of::<T>())?.downcast_mut::<T>().map(f)
+    }
+
+    // A panicking handler must not make the context unusable for teardown
+    fn lock(&self) -> MutexGuard<'_, AnyMap> {
+        self.values.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for G {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("G").field("len", &self.lock().len()).finish()
+    }
+}
+
+impl FromRequestParts for G {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(parts.g())
+    }
+}
+
+// Accessors for code that holds the raw request (or its parts, inside an
+// extractor)
+pub trait RequestExt {
+    fn state<S: Send + Sync + 'static>(&self) -> Option<Arc<S>>;
+
+    // Inserts an empty `G` if the request has none, so what is stored in it
+    // stays with the request
+    fn g(&mut self) -> G;
+
+    // Flask's `request.args`
+    fn args(&self) -> Args;
+}
+
+impl<B> RequestExt for Request<B> {
+    fn state<S: Send + Sync + 'static>(&self) -> Option<Arc<S>> {
+        self.extensions().get::<Arc<StateMap>>()?.get::<S>()
+    }
+
+    fn g(&mut self) -> G {
+        g(self.extensions_mut())
+    }
+
+    fn args(&self) -> Args {
+        Args::from_uri(self.uri())
+    }
+}
+
+impl RequestExt for Parts {
+    fn state<S: Send + Sync + 'static>(&self) -> Option<Arc<S>> {
+        self.extensions.get::<Arc<StateMap>>()?.get::<S>()
+    }
+
+    fn g(&mut self) -> G {
+        g(&mut self.extensions)
+    }
+
+    fn args(&self) -> Args {
+        Args::from_uri(&self.uri)
+    }
+}
+
+fn g(extensions: &mut Extensions) -> G {
+    match extensions.get::<G>() {
+        Some(g) => g.clone(),
+        None => {
+            let g = G::new();
+            extensions.insert(g.clone());
+            g
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Body, StatusCode};
+    use crate::testing::{get, send};
+    use crate::{handler, FlaskApp};
+
+    #[test]
+    fn test_g_is_shared_between_clones() {
+        let g = G::new();
+        let other = g.clone();
+        assert_eq!(g.insert(1u32), None);
+        assert_eq!(other.insert(2u32), Some(1));
+        assert_eq!(g.get::<u32>(), Some(2));
+
+        assert_eq!(g.with_mut(|n: &mut u32| { *n += 1; *n }), Some(3));
+        assert!(other.contains::<u32>());
+        assert_eq!(other.remove::<u32>(), Some(3));
+        assert!(!g.contains::<u32>());
+        assert_eq!(g.with_mut(|s: &mut String| s.len()), None);
+    }
+
+    #[test]
+    fn test_g_persists_on_requests_without_one() {
+        let mut req = Request::new(());
+        req.g().insert(7u32);
+        assert_eq!(req.g().get::<u32>(), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_request_context_g() {
+        #[derive(Clone)]
+        struct User(String);
+
+        // An extractor that authenticates and stashes the user for later code
+        struct Auth;
+        impl FromRequestParts for Auth {
+            type Rejection = StatusCode;
+
+            async fn from_request_parts(parts: &mut Parts) -> Result<Self, StatusCode> {
+                let name = parts.headers.get("x-user").ok_or(StatusCode::UNAUTHORIZED)?;
+                let user = User(name.to_str().unwrap_or_default().to_string());
+                parts.g().insert(user);
+                Ok(Auth)
+            }
+        }
+
+        let app = FlaskApp::new();
+        app.get("/me", handler(|_: Auth, g: G| async move {
+            g.get::<User>().map(|user| user.0).unwrap_or_default()
+        }))
+        .await;
+        app.get("/fresh", |mut req: Request<Body>, _params| {
+            format!("{}", req.g().contains::<User>())
+        })
+        .await;
+
+        let req = Request::builder().uri("/me").header("x-user", "ada").body(Body::empty()).unwrap();
+        assert_eq!(send(&app, req).await.body, "ada");
+        assert_eq!(get(&app, "/fresh").await.body, "false");
+    }
+}
@@ -0,0 +1,229 @@
+// Werkzeug-style converters that restrict what a `{name:converter}` segment
+// accepts. A route only matches when every converter accepts its segment.
+//
+//   {name} / {name:string}   any non-empty segment (the default)
+//   {id:int}                 unsigned decimal integer
+//   {price:float}            unsigned decimal with a fractional part, e.g. 1.5
+//   {key:uuid}               8-4-4-4-12 hexadecimal UUID
+//   {lang:any(en,de,fr)}     one of the listed values
+//   {code:regex([A-Z]{3})}   the whole segment matches the regular expression
+//   {file:path}              the rest of the path, slashes included
+//
+// Paths are split on '/' before converters run, so every converter except
+// `path` sees a single segment: a regex like `\d+/\d+`, which can only match
+// across segments, is rejected when the route is registered.
+//
+// Custom converters implement `Converter` and are registered by name with
+// `FlaskApp::register_converter` before any route uses them.
+use std::fmt;
+
+use regex::Regex;
+use regex_syntax::hir::{Hir, HirKind};
+
+pub trait Converter: Send + Sync + 'static {
+    fn accepts(&self, segment: &str) -> bool;
+}
+
+impl<F> Converter for F
+where
+    F: Fn(&str) -> bool + Send + Sync + 'static,
+{
+    fn accepts(&self, segment: &str) -> bool {
+        self(segment)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StringConverter;
+
+impl Converter for StringConverter {
+    fn accepts(&self, segment: &str) -> bool {
+        !segment.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IntConverter;
+
+impl Converter for IntConverter {
+    fn accepts(&self, segment: &str) -> bool {
+        !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FloatConverter;
+
+impl Converter for FloatConverter {
+    fn accepts(&self, segment: &str) -> bool {
+        match segment.split_once('.') {
+            Some((int, frac)) => IntConverter.accepts(int) && IntConverter.accepts(frac),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UuidConverter;
+
+impl Converter for UuidConverter {
+    fn accepts(&self, segment: &str) -> bool {
+        let groups: Vec<&str> = segment.split('-').collect();
+        groups.len() == 5
+            && groups
+                .iter()
+                .zip([8, 4, 4, 4, 12])
+                .all(|(group, len)| group.len() == len && group.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnyConverter {
+    values: Vec<String>,
+}
+
+impl AnyConverter {
+    pub fn new<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        AnyConverter {
+            values: values.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Converter for AnyConverter {
+    fn accepts(&self, segment: &str) -> bool {
+        self.values.iter().any(|value| value == segment)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RegexConverter {
+    regex: Regex,
+}
+
+impl RegexConverter {
+    // The expression is anchored so it must match the entire segment; one
+    // that only matches text containing '/' is an error
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+        let hir = regex_syntax::parse(pattern).map_err(|e| regex::Error::Syntax(e.to_string()))?;
+        if needs_slash(&hir) {
+            return Err(regex::Error::Syntax(format!(
+                "`{}` only matches across '/', but converters match a single path segment",
+                pattern
+            )));
+        }
+        Ok(RegexConverter { regex })
+    }
+}
+
+// Whether every match of `hir` contains a '/'
+fn needs_slash(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Literal(literal) => literal.0.contains(&b'/'),
+        HirKind::Capture(capture) => needs_slash(&capture.sub),
+        HirKind::Repetition(repetition) => repetition.min > 0 && needs_slash(&repetition.sub),
+        HirKind::Concat(hirs) => hirs.iter().any(needs_slash),
+        HirKind::Alternation(hirs) => hirs.iter().all(needs_slash),
+        HirKind::Empty | HirKind::Class(_) | HirKind::Look(_) => false,
+    }
+}
+
+impl Converter for RegexConverter {
+    fn accepts(&self, segment: &str) -> bool {
+        self.regex.is_match(segment)
+    }
+}
+
+// A parsed `{name:spec}` converter specification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ConverterSpec<'p> {
+    Default,
+    Path,
+    Named(&'p str),
+    Any(Vec<&'p str>),
+    Regex(&'p str),
+}
+
+impl<'p> ConverterSpec<'p> {
+    pub(crate) fn parse(spec: &'p str) -> Self {
+        let spec = spec.trim();
+        if spec.is_empty() || spec == "string" {
+            ConverterSpec::Default
+        } else if spec == "path" {
+            ConverterSpec::Path
+        } else if let Some(args) = spec.strip_prefix("any(").and_then(|s| s.strip_suffix(')')) {
+            ConverterSpec::Any(args.split(',').map(str::trim).collect())
+        } else if let Some(pattern) = spec.strip_prefix("regex(").and_then(|s| s.strip_suffix(')')) {
+            ConverterSpec::Regex(pattern)
+        } else {
+            ConverterSpec::Named(spec)
+        }
+    }
+}
+
+impl fmt::Display for ConverterSpec<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConverterSpec::Default => write!(f, "string"),
+            ConverterSpec::Path => write!(f, "path"),
+            ConverterSpec::Named(name) => write!(f, "{}", name),
+            ConverterSpec::Any(values) => write!(f, "any({})", values.join(",")),
+            ConverterSpec::Regex(pattern) => write!(f, "regex({})", pattern),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_converters() {
+        assert!(IntConverter.accepts("42"));
+        assert!(!IntConverter.accepts("-1"));
+        assert!(!IntConverter.accepts("4a"));
+
+        assert!(FloatConverter.accepts("1.5"));
+        assert!(!FloatConverter.accepts("1"));
+        assert!(!FloatConverter.accepts("1."));
+
+        assert!(UuidConverter.accepts("67e55044-10b1-426f-9247-bb680e5fe0c8"));
+        assert!(!UuidConverter.accepts("67e55044-10b1-426f-9247"));
+        assert!(!UuidConverter.accepts("67e55044-10b1-426f-9247-bb680e5fe0cg"));
+
+        let any = AnyConverter::new(["en", "de"]);
+        assert!(any.accepts("de"));
+        assert!(!any.accepts("fr"));
+
+        let regex = RegexConverter::new("[A-Z]{3}").unwrap();
+        assert!(regex.accepts("EUR"));
+        assert!(!regex.accepts("EURO"));
+    }
+
+    #[test]
+    fn test_regex_needing_a_slash_is_rejected() {
+        assert!(RegexConverter::new(r"\d+/\d+").is_err());
+        assert!(RegexConverter::new(r"(a|b)\/c").is_err());
+        assert!(RegexConverter::new("[/]").is_err());
+
+        // Expressions that merely allow '/' still match within a segment
+        assert!(RegexConverter::new(".+").unwrap().accepts("v1"));
+        assert!(RegexConverter::new("[^/]+").is_ok());
+        assert!(RegexConverter::new("a|b/c").is_ok());
+        assert!(RegexConverter::new("(/x)?y").is_ok());
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(ConverterSpec::parse(""), ConverterSpec::Default);
+        assert_eq!(ConverterSpec::parse("path"), ConverterSpec::Path);
+        assert_eq!(ConverterSpec::parse("int"), ConverterSpec::Named("int"));
+        assert_eq!(ConverterSpec::parse("any(a, b)"), ConverterSpec::Any(vec!["a", "b"]));
+        assert_eq!(ConverterSpec::parse("regex([a-z]{2})"), ConverterSpec::Regex("[a-z]{2}"));
+    }
+}
@@ -0,0 +1,259 @@
+// The HTML error page shown for 500s when `FlaskConfig::debug` is on.
+//
+// 500s from panics, errors from tower layers and a handler's `Err(e)` carry
+// an `ErrorReport` in their extensions. In debug mode the app swaps such a
+// response for a page with the error, its causes, the backtrace and the
+// request; in production the terse body is sent and the report dropped.
+// Like Flask's debugger, the page takes precedence over a registered 500
+// handler for panics.
+//
+// For a handler's `Err(e)` only the type of `e` is known when the report is
+// attached (its causes would need `E: Error`, which `IntoResponse` does not
+// require), so the page shows the type and the body `e` rendered to.
+use std::error::Error as StdError;
+use std::fmt::Write;
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::body;
+use crate::{MatchedRoute, RouteParams};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ErrorReport {
+    pub(crate) message: String,
+    // Causes, outermost first
+    pub(crate) chain: Vec<String>,
+    pub(crate) location: Option<String>,
+    pub(crate) backtrace: Option<String>,
+    // The type of a handler's `Err(e)`
+    pub(crate) error_type: Option<&'static str>,
+}
+
+impl ErrorReport {
+    pub(crate) fn from_error(error: &(dyn StdError + 'static)) -> Self {
+        let mut chain = Vec::new();
+        let mut source = error.source();
+        while let Some(cause) = source {
+            chain.push(cause.to_string());
+            source = cause.source();
+        }
+        ErrorReport {
+            message: error.to_string(),
+            chain,
+            ..ErrorReport::default()
+        }
+    }
+
+    // For a 500 raised from a value of type `E`; the message is taken from
+    // the response body when the page is rendered
+    pub(crate) fn raised<E>() -> Self {
+        ErrorReport {
+            error_type: Some(std::any::type_name::<E>()),
+            ..ErrorReport::default()
+        }
+    }
+}
+
+// Error bodies beyond this are cut off on the page
+const MESSAGE_LIMIT: usize = 16 * 1024;
+
+// Replaces a response carrying an `ErrorReport` with the debug page
+pub(crate) async fn render(head: &Request<()>, mut response: Response<Body>) -> Response<Body> {
+    let Some(mut report) = response.extensions_mut().remove::<ErrorReport>() else {
+        return response;
+    };
+    let status = response.status();
+    if report.message.is_empty() {
+        let body = body::to_bytes(response.into_body()).await.unwrap_or_default();
+        let body = String::from_utf8_lossy(&body[..body.len().min(MESSAGE_LIMIT)]).into_owned();
+        report.message = match body.trim() {
+            "" => status.canonical_reason().unwrap_or("").to_string(),
+            _ => body,
+        };
+    }
+    page(&report, head, status)
+}
+
+fn page(report: &ErrorReport, head: &Request<()>, status: StatusCode) -> Response<Body> {
+    let title = format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or(""));
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n\
+         body {{ font-family: sans-serif; margin: 2em; color: #222; }}\n\
+         h1 {{ color: #b00020; }}\n\
+         pre {{ background: #f6f6f6; padding: 1em; overflow-x: auto; }}\n\
+         table {{ border-collapse: collapse; }}\n\
+         td, th {{ border: 1px solid #ddd; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }}\n\
+         </style>\n</head>\n<body>\n<h1>{title}</h1>\n<h2>{message}</h2>\n",
+        title = escape(&title),
+        message = escape(&report.message),
+    );
+
+    if let Some(error_type) = report.error_type {
+        let _ = writeln!(html, "<p>returned as <code>{}</code></p>", escape(error_type));
+    }
+    if let Some(location) = &report.location {
+        let _ = writeln!(html, "<p>at <code>{}</code></p>", escape(location));
+    }
+    if !report.chain.is_empty() {
+        html.push_str("<h3>Caused by</h3>\n<ol>\n");
+        for cause in &report.chain {
+            let _ = writeln!(html, "<li>{}</li>", escape(cause));
+        }
+        html.push_str("</ol>\n");
+    }
+
+    html.push_str("<h3>Request</h3>\n<table>\n");
+    row(&mut html, "Method", head.method().as_str());
+    row(&mut html, "URL", &head.uri().to_string());
+    if let Some(matched) = head.extensions().get::<MatchedRoute>() {
+        row(&mut html, "Route", &matched.pattern);
+        if let Some(endpoint) = &matched.endpoint {
+            row(&mut html, "Endpoint", endpoint);
+        }
+    }
+    html.push_str("</table>\n");
+
+    if let Some(params) = head.extensions().get::<RouteParams>().filter(|params| !params.is_empty()) {
+        html.push_str("<h3>Route parameters</h3>\n<table>\n");
+        for (name, value) in params.iter() {
+            row(&mut html, name, value);
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h3>Headers</h3>\n<table>\n");
+    for (name, value) in head.headers() {
+        row(&mut html, name.as_str(), &String::from_utf8_lossy(value.as_bytes()));
+    }
+    html.push_str("</table>\n");
+
+    if let Some(backtrace) = &report.backtrace {
+        let _ = writeln!(html, "<h3>Backtrace</h3>\n<pre>{}</pre>", escape(backtrace));
+    }
+    html.push_str("</body>\n</html>\n");
+
+    let mut response = Response::new(Body::from(html));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    response
+}
+
+fn row(html: &mut String, name: &str, value: &str) {
+    let _ = writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", escape(name), escape(value));
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+    use crate::testing::get;
+    use crate::{FlaskApp, FlaskConfig, HttpError};
+
+    #[derive(Debug)]
+    struct Wrapped(std::io::Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "could not load <config>")
+        }
+    }
+
+    impl StdError for Wrapped {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_page_shows_error_chain_and_request() {
+        let error = Wrapped(std::io::Error::new(std::io::ErrorKind::NotFound, "app.toml missing"));
+        let report = ErrorReport::from_error(&error);
+        assert_eq!(report.chain, vec!["app.toml missing"]);
+
+        let mut head = Request::builder()
+            .method("POST")
+            .uri("/users/7?x=1")
+            .header("x-trace", "<abc>")
+            .body(())
+            .unwrap();
+        head.extensions_mut().insert(MatchedRoute {
+            pattern: "/users/{id}".to_string(),
+            ..MatchedRoute::default()
+        });
+        let mut params = RouteParams::new();
+        params.insert("id".to_string(), "7".to_string());
+        head.extensions_mut().insert(params);
+
+        let mut response = Response::new(Body::from("Internal Server Error"));
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response.extensions_mut().insert(report);
+        let response = render(&head, response).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.contains("<h2>could not load &lt;config&gt;</h2>"), "{}", html);
+        assert!(html.contains("<li>app.toml missing</li>"));
+        assert!(html.contains("<tr><th>URL</th><td>/users/7?x=1</td></tr>"));
+        assert!(html.contains("<tr><th>Route</th><td>/users/{id}</td></tr>"));
+        assert!(html.contains("<tr><th>id</th><td>7</td></tr>"));
+        assert!(html.contains("<tr><th>x-trace</th><td>&lt;abc&gt;</td></tr>"));
+    }
+
+    #[tokio::test]
+    async fn test_responses_without_a_report_pass_through() {
+        let head = Request::new(());
+        let response = render(&head, Response::new(Body::from("fine"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_debug_page_for_returned_500s() {
+        for debug in [false, true] {
+            let app = FlaskApp::with_config(FlaskConfig {
+                debug,
+                ..FlaskConfig::default()
+            });
+            app.get("/report", |_req, _params| async {
+                Err::<&str, _>(HttpError::new(StatusCode::INTERNAL_SERVER_ERROR).message("database <down>"))
+            })
+            .await;
+            app.get("/built", |_req, _params| (StatusCode::INTERNAL_SERVER_ERROR, "built by hand")).await;
+
+            let response = get(&app, "/report").await;
+            assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+            let text = response.body;
+            if debug {
+                assert!(text.contains("<h2>database &lt;down&gt;</h2>"), "{}", text);
+                assert!(text.contains("<code>rust_flask::error::HttpError</code>"), "{}", text);
+                assert!(text.contains("<tr><th>Route</th><td>/report</td></tr>"), "{}", text);
+            } else {
+                assert_eq!(text, "database <down>");
+            }
+
+            // A response the handler builds itself is not an error report
+            assert_eq!(get(&app, "/built").await.body, "built by hand");
+        }
+    }
+}
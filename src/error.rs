@@ -0,0 +1,200 @@
+// Flask's `abort()` and HTTPException: an error carrying the response it
+// should become, for leaving a handler early from any depth with `?`:
+//
+//   fn load_post(id: u64) -> Result<Post, HttpError> {
+//       posts().get(id).ok_or_else(|| abort(StatusCode::NOT_FOUND))
+//   }
+//   async fn show(Path(id): Path<u64>) -> Result<Json<Post>, HttpError> {
+//       Ok(Json(load_post(id)?))
+//   }
+//
+// Like any `Err` returned by a handler it goes through the app's error
+// handlers: `errorhandler_for::<HttpError>` first, then the handler
+// registered for its status.
+use std::fmt;
+
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use serde::Serialize;
+
+use crate::IntoResponse;
+
+// Boxed so `Result<T, HttpError>` stays small
+#[derive(Debug, Clone)]
+pub struct HttpError(Box<Inner>);
+
+#[derive(Debug, Clone)]
+struct Inner {
+    status: StatusCode,
+    message: Option<String>,
+    headers: HeaderMap,
+    payload: Option<serde_json::Value>,
+}
+
+// An `HttpError` with just a status, answered with its canonical reason
+pub fn abort(status: StatusCode) -> HttpError {
+    HttpError::new(status)
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode) -> Self {
+        HttpError(Box::new(Inner {
+            status,
+            message: None,
+            headers: HeaderMap::new(),
+            payload: None,
+        }))
+    }
+
+    // Sent as the plain text body instead of the canonical reason
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.0.message = Some(message.into());
+        self
+    }
+
+    // Panics on an invalid name or value, as `Response::builder` would fail
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: TryInto<HeaderName>,
+        K::Error: fmt::Debug,
+        V: TryInto<HeaderValue>,
+        V::Error: fmt::Debug,
+    {
+        let name = name.try_into().expect("invalid header name");
+        let value = value.try_into().expect("invalid header value");
+        self.0.headers.append(name, value);
+        self
+    }
+
+    // Sends `payload` as an `application/json` body instead of text
+    pub fn json<T: Serialize>(mut self, payload: &T) -> Self {
+        self.0.payload = Some(serde_json::to_value(payload).unwrap_or(serde_json::Value::Null));
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.0.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.0.headers
+    }
+
+    pub fn payload(&self) -> Option<&serde_json::Value> {
+        self.0.payload.as_ref()
+    }
+
+    // The message, or the status's canonical reason without one
+    pub fn description(&self) -> &str {
+        self.0
+            .message
+            .as_deref()
+            .unwrap_or_else(|| self.0.status.canonical_reason().unwrap_or(""))
+    }
+}
+
+impl From<StatusCode> for HttpError {
+    fn from(status: StatusCode) -> Self {
+        HttpError::new(status)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.0.status, self.description())
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response<Body> {
+        let mut response = match &self.0.payload {
+            Some(payload) => {
+                let mut response = Response::new(Body::from(payload.to_string()));
+                response.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("application/json; charset=utf-8"),
+                );
+                response
+            }
+            None => self.description().to_string().into_response(),
+        };
+        *response.status_mut() = self.0.status;
+        response.headers_mut().extend(self.0.headers);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+    use crate::testing::{get, send};
+    use crate::FlaskApp;
+
+    async fn body_string(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_http_error_responses() {
+        let response = abort(StatusCode::FORBIDDEN).into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(body_string(response).await, "Forbidden");
+
+        let error = HttpError::new(StatusCode::UNAUTHORIZED)
+            .message("Log in first")
+            .header("WWW-Authenticate", "Bearer");
+        assert_eq!(error.to_string(), "401 Unauthorized: Log in first");
+        let response = error.into_response();
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(body_string(response).await, "Log in first");
+
+        let response = HttpError::new(StatusCode::CONFLICT)
+            .json(&serde_json::json!({"error": "name taken"}))
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json; charset=utf-8");
+        assert_eq!(body_string(response).await, r#"{"error":"name taken"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_abort_and_http_error() {
+        fn require_admin(req: &Request<Body>) -> Result<(), HttpError> {
+            match req.headers().get("x-role").and_then(|v| v.to_str().ok()) {
+                Some("admin") => Ok(()),
+                Some(_) => Err(abort(StatusCode::FORBIDDEN)),
+                None => Err(HttpError::new(StatusCode::UNAUTHORIZED)
+                    .message("Log in first")
+                    .header("WWW-Authenticate", "Bearer")),
+            }
+        }
+
+        let app = FlaskApp::new();
+        app.errorhandler(StatusCode::FORBIDDEN, |_req, _response| async {
+            (StatusCode::FORBIDDEN, "admins only")
+        })
+        .await;
+        app.get("/admin", |req, _params| async move {
+            require_admin(&req)?;
+            Ok::<_, HttpError>("welcome")
+        })
+        .await;
+
+        let as_role = |role: &str| Request::builder().uri("/admin").header("x-role", role).body(Body::empty()).unwrap();
+
+        assert_eq!(send(&app, as_role("admin")).await.status, StatusCode::OK);
+        let response = send(&app, as_role("guest")).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.body, "admins only");
+
+        // Headers and message survive when no handler is registered
+        let response = get(&app, "/admin").await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.header("WWW-Authenticate"), Some("Bearer"));
+        assert_eq!(response.body, "Log in first");
+    }
+}
@@ -0,0 +1,313 @@
+// Flask's `errorhandler`: custom responses for errors, by status code or by
+// error type.
+//
+//   app.errorhandler(StatusCode::NOT_FOUND, |req, _response| async move {
+//       (StatusCode::NOT_FOUND, format!("Nothing at {}", req.uri().path()))
+//   }).await;
+//   app.errorhandler_for(|e: JsonError| (StatusCode::BAD_REQUEST, e.to_string())).await;
+//
+// A type handler receives the `E` of a handler's `Err(E)` or of an
+// extractor's rejection before it becomes a response. Status handlers get
+// the request head and the error response, and apply to the framework's own
+// errors (404, 405, 413), to `None` from a handler and to errors with no
+// type handler. Neither runs for a response a handler builds itself. Like
+// Flask, the status of the returned value is used as is, so return it along
+// with the body.
+//
+// Handlers registered on a blueprint take precedence for requests routed to
+// it (nested blueprints before their parents) over the app's handlers.
+//
+// `raise` finds the type handlers through a task-local set while the route's
+// handler runs. The request also carries them, so the handler sees them
+// even when a route layer such as tower's `Buffer` polls it on another task;
+// an `Err` turned into a response on a task the handler spawns itself gets
+// the plain response of `E`.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::debug_page::ErrorReport;
+use crate::handler::BoxFuture;
+use crate::hooks::{self, applicable, rescope, Scoped};
+use crate::IntoResponse;
+
+pub(crate) type StatusHandler = Arc<dyn Fn(Request<()>, Response<Body>) -> BoxFuture<Response<Body>> + Send + Sync>;
+pub(crate) type TypeHandler = Arc<dyn Fn(Box<dyn Any>) -> Response<Body> + Send + Sync>;
+
+pub(crate) fn status<F, Fut>(handler: F) -> StatusHandler
+where
+    F: Fn(Request<()>, Response<Body>) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    Arc::new(move |req, response| {
+        let future = handler(req, response);
+        Box::pin(async move { future.await.into_response() })
+    })
+}
+
+pub(crate) fn typed<E, F, R>(handler: F) -> (TypeId, TypeHandler)
+where
+    E: 'static,
+    F: Fn(E) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    let handler: TypeHandler = Arc::new(move |error: Box<dyn Any>| {
+        let error = error.downcast::<E>().expect("type handlers are looked up by TypeId");
+        handler(*error).into_response()
+    });
+    (TypeId::of::<E>(), handler)
+}
+
+#[derive(Default)]
+pub(crate) struct ErrorHandlers {
+    by_status: Vec<Scoped<(StatusCode, StatusHandler)>>,
+    by_type: Vec<Scoped<(TypeId, TypeHandler)>>,
+}
+
+impl ErrorHandlers {
+    pub(crate) fn add_status(&mut self, status: StatusCode, handler: StatusHandler) {
+        self.by_status.push(Scoped {
+            blueprint: None,
+            hook: (status, handler),
+        });
+    }
+
+    pub(crate) fn add_type(&mut self, (type_id, handler): (TypeId, TypeHandler)) {
+        self.by_type.push(Scoped {
+            blueprint: None,
+            hook: (type_id, handler),
+        });
+    }
+
+    pub(crate) fn extend_scoped(&mut self, handlers: ErrorHandlers, blueprint: &str) {
+        let blueprint: Arc<str> = Arc::from(blueprint);
+        self.by_status.extend(rescope(handlers.by_status, &blueprint));
+        self.by_type.extend(rescope(handlers.by_type, &blueprint));
+    }
+
+    pub(crate) fn extend(&mut self, handlers: ErrorHandlers) {
+        self.by_status.extend(handlers.by_status);
+        self.by_type.extend(handlers.by_type);
+    }
+
+    // One handler per status and type for a request routed to `blueprint`:
+    // the innermost blueprint's wins, and a later registration replaces an
+    // earlier one in the same scope
+    pub(crate) fn for_request(&self, blueprint: Option<&str>) -> RequestErrorHandlers {
+        RequestErrorHandlers {
+            by_status: applicable(&self.by_status, blueprint).into_iter().collect(),
+            by_type: applicable(&self.by_type, blueprint).into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RequestErrorHandlers {
+    by_status: HashMap<StatusCode, StatusHandler>,
+    by_type: HashMap<TypeId, TypeHandler>,
+}
+
+impl RequestErrorHandlers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.by_status.is_empty() && self.by_type.is_empty()
+    }
+
+    // Replaces an error response with the output of its status handler,
+    // which stays marked for teardown hooks
+    pub(crate) async fn handle(&self, head: &Request<()>, response: Response<Body>) -> Response<Body> {
+        if response.extensions().get::<Raised>().is_none() {
+            return response;
+        }
+        match self.by_status.get(&response.status()) {
+            Some(handler) => mark(handler(hooks::request_head(head), response).await),
+            None => response,
+        }
+    }
+}
+
+tokio::task_local! {
+    // The handlers for the request being dispatched on this task
+    static CURRENT: Arc<RequestErrorHandlers>;
+}
+
+// Runs `future` with `handlers` available to `raise`
+pub(crate) async fn scope<T>(handlers: Arc<RequestErrorHandlers>, future: impl Future<Output = T>) -> T {
+    CURRENT.scope(handlers, future).await
+}
+
+// Calls a route handler with the request's handlers, taken from its
+// extensions, available to `raise` on whatever task polls it
+pub(crate) fn within(
+    handlers: Option<Arc<RequestErrorHandlers>>,
+    call: impl FnOnce() -> BoxFuture<Response<Body>>,
+) -> BoxFuture<Response<Body>> {
+    match handlers {
+        Some(handlers) => {
+            let future = CURRENT.sync_scope(Arc::clone(&handlers), call);
+            Box::pin(CURRENT.scope(handlers, future))
+        }
+        None => call(),
+    }
+}
+
+// Marks a response as an error that status handlers may replace
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Raised;
+
+pub(crate) fn mark(mut response: Response<Body>) -> Response<Body> {
+    response.extensions_mut().insert(Raised);
+    response
+}
+
+// What teardown hooks are told about an error response, e.g.
+// "404 Not Found" or "500 Internal Server Error (returned as app::DbError)"
+pub(crate) fn describe(response: &Response<Body>) -> Option<String> {
+    response.extensions().get::<Raised>()?;
+    let error_type = response.extensions().get::<ErrorReport>().and_then(|report| report.error_type);
+    Some(match error_type {
+        Some(error_type) => format!("{} (returned as {})", response.status(), error_type),
+        None => response.status().to_string(),
+    })
+}
+
+// Turns an error into its response, through the request's handler for `E`
+// when there is one. A 500 is reported on the debug page.
+pub(crate) fn raise<E: IntoResponse + 'static>(error: E) -> Response<Body> {
+    let handler = CURRENT
+        .try_with(|handlers| handlers.by_type.get(&TypeId::of::<E>()).cloned())
+        .ok()
+        .flatten();
+    let mut response = match handler {
+        Some(handler) => handler(Box::new(error)),
+        None => mark(error.into_response()),
+    };
+    if response.status() == StatusCode::INTERNAL_SERVER_ERROR && response.extensions().get::<ErrorReport>().is_none() {
+        response.extensions_mut().insert(ErrorReport::raised::<E>());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::CONTENT_TYPE;
+    use hyper::Method;
+    use crate::testing::{get, send};
+    use crate::{handler, Blueprint, FlaskApp, Json, JsonError, Next, Rule};
+
+    #[derive(Debug)]
+    struct Teapot;
+
+    impl IntoResponse for Teapot {
+        fn into_response(self) -> Response<Body> {
+            StatusCode::IM_A_TEAPOT.into_response()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_innermost_blueprint_handler_wins() {
+        let mut app = ErrorHandlers::default();
+        app.add_type(typed(|_: Teapot| "app"));
+
+        let mut api = ErrorHandlers::default();
+        api.add_type(typed(|_: Teapot| "api"));
+        let mut users = ErrorHandlers::default();
+        users.add_type(typed(|_: Teapot| "users"));
+        app.extend_scoped(users, "api.users");
+        app.extend_scoped(api, "api");
+
+        let body = |blueprint: Option<&'static str>| {
+            let handlers = Arc::new(app.for_request(blueprint));
+            async move {
+                let response = scope(handlers, async { raise(Teapot) }).await;
+                hyper::body::to_bytes(response.into_body()).await.unwrap()
+            }
+        };
+        assert_eq!(&body(None).await[..], b"app");
+        assert_eq!(&body(Some("admin")).await[..], b"app");
+        assert_eq!(&body(Some("api")).await[..], b"api");
+        assert_eq!(&body(Some("api.users")).await[..], b"users");
+    }
+
+    #[test]
+    fn test_raise_outside_a_request_uses_the_default() {
+        let response = raise(Teapot);
+        assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+        assert!(response.extensions().get::<Raised>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_error_handlers() {
+        #[derive(Debug)]
+        struct OutOfStock(&'static str);
+
+        impl IntoResponse for OutOfStock {
+            fn into_response(self) -> Response<Body> {
+                (StatusCode::CONFLICT, "out of stock").into_response()
+            }
+        }
+
+        let app = FlaskApp::new();
+        app.errorhandler(StatusCode::NOT_FOUND, |req, _response| async move {
+            (StatusCode::NOT_FOUND, format!("no page at {}", req.uri().path()))
+        })
+        .await;
+        app.errorhandler_for(|e: OutOfStock| (StatusCode::CONFLICT, format!("app: {} is gone", e.0))).await;
+        app.errorhandler_for(|e: JsonError| (e.status(), "bad json")).await;
+
+        app.post("/orders", handler(|Json(item): Json<String>| async move {
+            if item == "tea" { Ok("ordered") } else { Err(OutOfStock("coffee")) }
+        }))
+        .await;
+        app.get("/users/{id}", |_req, params| params.get("id").filter(|id| *id == "1").map(str::to_string))
+            .await;
+        app.get("/gone", |_req, _params| (StatusCode::NOT_FOUND, "handler's own 404")).await;
+        // The handler runs on another task, as behind tower's `Buffer`
+        let spawned = Rule::new("/spawned").middleware(|req: Request<Body>, next: Next| async move {
+            tokio::spawn(next.run(req)).await.unwrap()
+        });
+        app.get(spawned, |_req, _params| Err::<&str, _>(OutOfStock("tea"))).await;
+
+        let mut shop = Blueprint::new("shop");
+        shop.errorhandler_for(|e: OutOfStock| (StatusCode::CONFLICT, format!("shop: {} is gone", e.0)));
+        shop.get("/buy", |_req, _params| async { Err::<&str, _>(OutOfStock("milk")) });
+        app.register_blueprint(shop, "/shop").await;
+
+        let fetch = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let response = get(app, uri).await;
+                (response.status, response.body)
+            }
+        };
+        let order = |body: &'static str| {
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri("/orders")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let app = &app;
+            async move {
+                let response = send(app, req).await;
+                (response.status, response.body)
+            }
+        };
+
+        assert_eq!(fetch("/missing").await, (StatusCode::NOT_FOUND, "no page at /missing".into()));
+        assert_eq!(fetch("/users/2").await, (StatusCode::NOT_FOUND, "no page at /users/2".into()));
+        assert_eq!(fetch("/gone").await, (StatusCode::NOT_FOUND, "handler's own 404".into()));
+        assert_eq!(fetch("/spawned").await, (StatusCode::CONFLICT, "app: tea is gone".into()));
+
+        assert_eq!(order("\"tea\"").await, (StatusCode::OK, "ordered".into()));
+        assert_eq!(order("\"cake\"").await, (StatusCode::CONFLICT, "app: coffee is gone".into()));
+        assert_eq!(order("\"tea").await, (StatusCode::BAD_REQUEST, "bad json".into()));
+
+        // Blueprint handlers win for their own routes
+        assert_eq!(fetch("/shop/buy").await, (StatusCode::CONFLICT, "shop: milk is gone".into()));
+    }
+}
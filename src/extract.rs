@@ -0,0 +1,254 @@
+// Typed extractors for handler arguments.
+//
+// Extractors that only need the request head implement `FromRequestParts`
+// and may appear in any argument position; extractors that consume the body
+// implement `FromRequest` and must be the last argument. Every extractor
+// names a `Rejection` that is turned into the response when extraction fails.
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::http::request::Parts;
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode, Uri};
+use serde::de::DeserializeOwned;
+
+use crate::body;
+use crate::query::{self, QueryError};
+use crate::{IntoResponse, Json, JsonError, RouteParams};
+
+pub trait FromRequestParts: Sized {
+    type Rejection: IntoResponse + 'static;
+
+    fn from_request_parts(
+        parts: &mut Parts,
+    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
+}
+
+// `M` only distinguishes body extractors from the blanket impl that lets any
+// `FromRequestParts` extractor sit in the last argument position.
+pub trait FromRequest<M = private::ViaRequest>: Sized {
+    type Rejection: IntoResponse + 'static;
+
+    fn from_request(req: Request<Body>) -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
+}
+
+mod private {
+    pub enum ViaParts {}
+    pub enum ViaRequest {}
+}
+
+impl<T: FromRequestParts> FromRequest<private::ViaParts> for T {
+    type Rejection = T::Rejection;
+
+    async fn from_request(req: Request<Body>) -> Result<Self, Self::Rejection> {
+        let (mut parts, _body) = req.into_parts();
+        T::from_request_parts(&mut parts).await
+    }
+}
+
+// Generic extraction failure: a status code plus a human readable message
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    status: StatusCode,
+    message: String,
+}
+
+impl Rejection {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Rejection {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response<Body> {
+        (self.status, self.message).into_response()
+    }
+}
+
+impl FromRequestParts for HeaderMap {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(parts.headers.clone())
+    }
+}
+
+impl FromRequestParts for Method {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(parts.method.clone())
+    }
+}
+
+impl FromRequestParts for Uri {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(parts.uri.clone())
+    }
+}
+
+impl FromRequestParts for RouteParams {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<RouteParams>().cloned().unwrap_or_default())
+    }
+}
+
+impl FromRequest for Request<Body> {
+    type Rejection = Infallible;
+
+    async fn from_request(req: Request<Body>) -> Result<Self, Self::Rejection> {
+        Ok(req)
+    }
+}
+
+impl<T: DeserializeOwned + Send> FromRequest for Json<T> {
+    type Rejection = JsonError;
+
+    async fn from_request(mut req: Request<Body>) -> Result<Self, Self::Rejection> {
+        // Resolves to the inherent `Json::from_request(&mut Request<Body>)`
+        Json::<T>::from_request(&mut req).await
+    }
+}
+
+// Deserializes the URL query string, e.g. `?page=2&per_page=20`. Use `Args`
+// for repeated keys or untyped access.
+#[derive(Debug, Clone)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned + Send> FromRequestParts for Query<T> {
+    type Rejection = QueryError;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        query::deserialize(parts.uri.query().unwrap_or("")).map(Query)
+    }
+}
+
+// Deserializes an `application/x-www-form-urlencoded` request body
+#[derive(Debug, Clone)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned + Send> FromRequest for Form<T> {
+    type Rejection = Rejection;
+
+    async fn from_request(req: Request<Body>) -> Result<Self, Self::Rejection> {
+        if !has_content_type(req.headers(), "application/x-www-form-urlencoded") {
+            return Err(Rejection::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/x-www-form-urlencoded`",
+            ));
+        }
+
+        let body = body::to_bytes(req.into_body())
+            .await
+            .map_err(|e| Rejection::new(e.status(), e.to_string()))?;
+
+        serde_urlencoded::from_bytes(&body)
+            .map(Form)
+            .map_err(|e| Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid form data: {}", e)))
+    }
+}
+
+// Compares the media type only, ignoring parameters such as `charset`
+pub(crate) fn has_content_type(headers: &HeaderMap, expected: &str) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|media_type| media_type.trim().eq_ignore_ascii_case(expected))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Pagination {
+        page: u32,
+        per_page: Option<u32>,
+    }
+
+    fn parts(uri: &str) -> Parts {
+        Request::builder().uri(uri).body(()).unwrap().into_parts().0
+    }
+
+    #[tokio::test]
+    async fn test_query_extractor() {
+        let Query(p) = Query::<Pagination>::from_request_parts(&mut parts("/items?page=2&per_page=5"))
+            .await
+            .unwrap();
+        assert_eq!((p.page, p.per_page), (2, Some(5)));
+
+        let err = Query::<Pagination>::from_request_parts(&mut parts("/items?page=two"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.name(), Some("page"));
+
+        let err = Query::<Pagination>::from_request_parts(&mut parts("/items?per_page=1"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid query string: missing field `page`");
+
+        #[derive(Debug, Deserialize)]
+        struct Search {
+            q: String,
+        }
+        let Query(s) = Query::<Search>::from_request_parts(&mut parts("/search?q=caf%C3%A9+au+lait"))
+            .await
+            .unwrap();
+        assert_eq!(s.q, "café au lait");
+    }
+
+    #[tokio::test]
+    async fn test_form_requires_content_type() {
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")
+            .body(Body::from("page=3"))
+            .unwrap();
+        let Form(p) = Form::<Pagination>::from_request(req).await.unwrap();
+        assert_eq!(p.page, 3);
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "text/plain")
+            .body(Body::from("page=3"))
+            .unwrap();
+        let err = Form::<Pagination>::from_request(req).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from("per_page=3"))
+            .unwrap();
+        let err = Form::<Pagination>::from_request(req).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,241 @@
+// Adapters that turn user closures and async fns into the boxed handler type
+// stored in the router.
+//
+// `get`/`post`/`route` keep an explicit `Fn(Request<Body>, RouteParams) -> R`
+// bound so closure arguments are still inferred, and let `R` be either a
+// ready `IntoResponse` value or a future of one. The marker type parameter
+// on `HandlerOutput` keeps the two impls from overlapping.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response};
+
+use crate::errorhandlers::{self, RequestErrorHandlers};
+use crate::extract::{FromRequest, FromRequestParts};
+use crate::{IntoResponse, RouteParams};
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+pub(crate) type RouteHandler =
+    Arc<dyn Fn(Request<Body>, RouteParams) -> BoxFuture<Response<Body>> + Send + Sync>;
+
+// Marker for handlers that return their response directly
+pub enum Immediate {}
+
+// Marker for handlers that return a future resolving to the response
+pub enum Deferred {}
+
+pub trait HandlerOutput<M>: Send + 'static {
+    fn into_future(self) -> BoxFuture<Response<Body>>;
+}
+
+impl<R> HandlerOutput<Immediate> for R
+where
+    R: IntoResponse + Send + 'static,
+{
+    fn into_future(self) -> BoxFuture<Response<Body>> {
+        let response = self.into_response();
+        Box::pin(async move { response })
+    }
+}
+
+impl<F> HandlerOutput<Deferred> for F
+where
+    F: Future + Send + 'static,
+    F::Output: IntoResponse,
+{
+    fn into_future(self) -> BoxFuture<Response<Body>> {
+        Box::pin(async move { self.await.into_response() })
+    }
+}
+
+pub(crate) fn boxed<F, R, M>(handler: F) -> RouteHandler
+where
+    F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+    R: HandlerOutput<M>,
+{
+    Arc::new(move |req, params| {
+        let handlers = req.extensions().get::<Arc<RequestErrorHandlers>>().cloned();
+        errorhandlers::within(handlers, || handler(req, params).into_future())
+    })
+}
+
+// Handlers whose arguments are extractors, e.g.
+// `async fn show(Query(page): Query<Page>, headers: HeaderMap) -> impl IntoResponse`.
+// Wrap them with `handler(...)` to register them on a FlaskApp.
+pub trait Handler<T>: Send + Sync + 'static {
+    fn call(self: Arc<Self>, req: Request<Body>) -> BoxFuture<Response<Body>>;
+}
+
+impl<F, Fut> Handler<()> for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    fn call(self: Arc<Self>, _req: Request<Body>) -> BoxFuture<Response<Body>> {
+        Box::pin(async move { self().await.into_response() })
+    }
+}
+
+macro_rules! impl_handler {
+    ($($ty:ident),* ; $last:ident) => {
+        #[allow(non_snake_case)]
+        impl<F, Fut, M, $($ty,)* $last> Handler<(M, $($ty,)* $last,)> for F
+        where
+            F: Fn($($ty,)* $last) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            $($ty: FromRequestParts + Send + 'static,)*
+            $last: FromRequest<M> + Send + 'static,
+            M: 'static,
+        {
+            fn call(self: Arc<Self>, req: Request<Body>) -> BoxFuture<Response<Body>> {
+                Box::pin(async move {
+                    #[allow(unused_mut)]
+                    let (mut parts, body) = req.into_parts();
+                    $(
+                        let $ty = match $ty::from_request_parts(&mut parts).await {
+                            Ok(value) => value,
+                            Err(rejection) => return errorhandlers::raise(rejection),
+                        };
+                    )*
+                    let req = Request::from_parts(parts, body);
+                    let $last = match $last::from_request(req).await {
+                        Ok(value) => value,
+                        Err(rejection) => return errorhandlers::raise(rejection),
+                    };
+                    self($($ty,)* $last).await.into_response()
+                })
+            }
+        }
+    };
+}
+
+impl_handler!(; T1);
+impl_handler!(T1; T2);
+impl_handler!(T1, T2; T3);
+impl_handler!(T1, T2, T3; T4);
+impl_handler!(T1, T2, T3, T4; T5);
+impl_handler!(T1, T2, T3, T4, T5; T6);
+impl_handler!(T1, T2, T3, T4, T5, T6; T7);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7; T8);
+
+// Adapts an extractor-based handler to the `(Request<Body>, RouteParams)`
+// shape accepted by `get`/`post`/`route`.
+pub fn handler<H, T>(handler: H) -> impl Fn(Request<Body>, RouteParams) -> BoxFuture<Response<Body>> + Send + Sync + 'static
+where
+    H: Handler<T>,
+{
+    let handler = Arc::new(handler);
+    move |mut req, params| {
+        req.extensions_mut().insert(params);
+        Arc::clone(&handler).call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::CONTENT_TYPE;
+    use hyper::{Method, StatusCode};
+    use serde::Deserialize;
+
+    use crate::testing::send;
+    use crate::{text_response, FlaskApp, Json, Query};
+
+    #[tokio::test]
+    async fn test_async_closure_handler() {
+        let app = FlaskApp::new();
+        app.post("/echo", |mut req, _params| async move {
+            let body = hyper::body::to_bytes(req.body_mut()).await.unwrap();
+            text_response(std::str::from_utf8(&body).unwrap())
+        })
+        .await;
+
+        let req = Request::builder().method(Method::POST).uri("/echo").body(Body::from("ping")).unwrap();
+        assert_eq!(send(&app, req).await.body, "ping");
+    }
+
+    #[tokio::test]
+    async fn test_async_fn_handler() {
+        #[derive(Deserialize)]
+        struct Greeting {
+            name: String,
+        }
+
+        async fn greet(mut req: Request<Body>, _params: RouteParams) -> Response<Body> {
+            match Json::<Greeting>::from_request(&mut req).await {
+                Ok(Json(greeting)) => text_response(&format!("Hello, {}!", greeting.name)),
+                Err(e) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(e.to_string()))
+                    .unwrap(),
+            }
+        }
+
+        let app = FlaskApp::new();
+        app.post("/greet", greet).await;
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/greet")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"name": "Ferris"}"#))
+            .unwrap();
+        let response = send(&app, req).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "Hello, Ferris!");
+    }
+
+    #[tokio::test]
+    async fn test_extractor_handler() {
+        #[derive(Deserialize)]
+        struct Search {
+            q: String,
+        }
+
+        #[derive(Deserialize)]
+        struct Filters {
+            limit: u32,
+        }
+
+        async fn search(
+            method: Method,
+            headers: hyper::HeaderMap,
+            Query(search): Query<Search>,
+            Json(filters): Json<Filters>,
+        ) -> String {
+            let agent = headers.get("User-Agent").and_then(|v| v.to_str().ok()).unwrap_or("-");
+            format!("{} {} {} {}", method, search.q, filters.limit, agent)
+        }
+
+        let app = FlaskApp::new();
+        app.post("/search", handler(search)).await;
+
+        let post = |uri: &str, body: &'static str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header("User-Agent", "test")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = send(&app, post("/search?q=rust", r#"{"limit": 10}"#)).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "POST rust 10 test");
+
+        // Rejections short-circuit with their own status codes
+        let cases = [
+            ("/search", r#"{"limit": 10}"#, StatusCode::BAD_REQUEST),
+            ("/search?q=rust", r#"{"limit": "#, StatusCode::BAD_REQUEST),
+            ("/search?q=rust", r#"{"limit": "ten"}"#, StatusCode::UNPROCESSABLE_ENTITY),
+        ];
+        for (uri, body, status) in cases {
+            assert_eq!(send(&app, post(uri, body)).await.status, status, "{} {}", uri, body);
+        }
+    }
+}
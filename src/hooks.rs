@@ -0,0 +1,382 @@
+// Flask's request hooks.
+//
+//   before_request    runs before the handler; returning `Err(response)`
+//                     skips the handler and answers with that response
+//   after_request     receives the outgoing response and returns the one to
+//                     send, e.g. with extra headers
+//   teardown_request  always runs last, also when a hook or the handler
+//                     panicked or errored, with the panic message or a
+//                     description of the error response, and when the
+//                     request was dropped before it finished (a timeout
+//                     layer, a client disconnect) with "request cancelled"
+//
+// App hooks run for every request, blueprint hooks only for requests routed
+// to that blueprint or one nested in it. Before hooks run app first, then
+// parent blueprints before children; after and teardown hooks run in exactly
+// the reverse order.
+//
+// After and teardown hooks get a `Request<()>` copy of the request head whose
+// extensions hold the request's `G`, state and `MatchedRoute`.
+use std::future::Future;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response};
+
+use crate::context::G;
+use crate::handler::BoxFuture;
+use crate::panic::CatchUnwind;
+use crate::state::StateMap;
+use crate::{MatchedRoute, RouteParams};
+
+pub(crate) type BeforeHook =
+    Arc<dyn Fn(Request<Body>) -> BoxFuture<Result<Request<Body>, Response<Body>>> + Send + Sync>;
+pub(crate) type AfterHook = Arc<dyn Fn(Request<()>, Response<Body>) -> BoxFuture<Response<Body>> + Send + Sync>;
+pub(crate) type TeardownHook = Arc<dyn Fn(Request<()>, Option<String>) -> BoxFuture<()> + Send + Sync>;
+
+pub(crate) fn before<F, Fut>(hook: F) -> BeforeHook
+where
+    F: Fn(Request<Body>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Request<Body>, Response<Body>>> + Send + 'static,
+{
+    Arc::new(move |req| Box::pin(hook(req)))
+}
+
+pub(crate) fn after<F, Fut>(hook: F) -> AfterHook
+where
+    F: Fn(Request<()>, Response<Body>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    Arc::new(move |req, response| Box::pin(hook(req, response)))
+}
+
+pub(crate) fn teardown<F, Fut>(hook: F) -> TeardownHook
+where
+    F: Fn(Request<()>, Option<String>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Arc::new(move |req, error| Box::pin(hook(req, error)))
+}
+
+// A hook plus the dotted name of the blueprint it belongs to (None: the app)
+pub(crate) struct Scoped<H> {
+    pub(crate) blueprint: Option<Arc<str>>,
+    pub(crate) hook: H,
+}
+
+#[derive(Default)]
+pub(crate) struct Hooks {
+    before: Vec<Scoped<BeforeHook>>,
+    after: Vec<Scoped<AfterHook>>,
+    teardown: Vec<Scoped<TeardownHook>>,
+}
+
+impl Hooks {
+    pub(crate) fn add_before(&mut self, hook: BeforeHook) {
+        self.before.push(Scoped { blueprint: None, hook });
+    }
+
+    pub(crate) fn add_after(&mut self, hook: AfterHook) {
+        self.after.push(Scoped { blueprint: None, hook });
+    }
+
+    pub(crate) fn add_teardown(&mut self, hook: TeardownHook) {
+        self.teardown.push(Scoped { blueprint: None, hook });
+    }
+
+    // Moves a blueprint's own hooks in, scoped to its dotted name
+    pub(crate) fn extend_scoped(&mut self, hooks: Hooks, blueprint: &str) {
+        let blueprint: Arc<str> = Arc::from(blueprint);
+        self.before.extend(rescope(hooks.before, &blueprint));
+        self.after.extend(rescope(hooks.after, &blueprint));
+        self.teardown.extend(rescope(hooks.teardown, &blueprint));
+    }
+
+    pub(crate) fn extend(&mut self, hooks: Hooks) {
+        self.before.extend(hooks.before);
+        self.after.extend(hooks.after);
+        self.teardown.extend(hooks.teardown);
+    }
+
+    // The hooks that apply to a request routed to `blueprint`, in run order
+    pub(crate) fn for_request(&self, blueprint: Option<&str>) -> RequestHooks {
+        let mut after = applicable(&self.after, blueprint);
+        after.reverse();
+        let mut teardown = applicable(&self.teardown, blueprint);
+        teardown.reverse();
+        RequestHooks {
+            before: applicable(&self.before, blueprint),
+            after,
+            teardown,
+        }
+    }
+}
+
+pub(crate) fn rescope<H>(hooks: Vec<Scoped<H>>, blueprint: &Arc<str>) -> Vec<Scoped<H>> {
+    hooks
+        .into_iter()
+        .map(|scoped| Scoped {
+            blueprint: Some(Arc::clone(blueprint)),
+            hook: scoped.hook,
+        })
+        .collect()
+}
+
+pub(crate) struct RequestHooks {
+    pub(crate) before: Vec<BeforeHook>,
+    pub(crate) after: Vec<AfterHook>,
+    pub(crate) teardown: Vec<TeardownHook>,
+}
+
+// Runs the teardown hooks once: through `run` when the request finishes,
+// otherwise when dropped, on a spawned task
+pub(crate) struct Teardown {
+    hooks: std::vec::IntoIter<TeardownHook>,
+    head: Request<()>,
+}
+
+impl Teardown {
+    pub(crate) fn new(hooks: Vec<TeardownHook>, head: Request<()>) -> Self {
+        Teardown {
+            hooks: hooks.into_iter(),
+            head,
+        }
+    }
+
+    // A panicking hook is only logged and the remaining hooks still run
+    pub(crate) async fn run(&mut self, error: Option<String>) {
+        for hook in self.hooks.by_ref() {
+            run_teardown(hook, request_head(&self.head), error.clone()).await;
+        }
+    }
+}
+
+impl Drop for Teardown {
+    fn drop(&mut self) {
+        let hooks: Vec<_> = self.hooks.by_ref().collect();
+        if hooks.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let mut rest = Teardown::new(hooks, request_head(&self.head));
+        runtime.spawn(async move { rest.run(Some("request cancelled".to_string())).await });
+    }
+}
+
+async fn run_teardown(hook: TeardownHook, head: Request<()>, error: Option<String>) {
+    let (method, uri) = (head.method().clone(), head.uri().clone());
+    if let Err(panic) = CatchUnwind::new(Box::pin(async move { hook(head, error).await })).await {
+        eprintln!("Panic in teardown hook for {} {}: {}", method, uri, panic);
+    }
+}
+
+// Threads the request through the before hooks until one answers
+pub(crate) async fn run_before(hooks: &[BeforeHook], mut req: Request<Body>) -> Result<Request<Body>, Response<Body>> {
+    for hook in hooks {
+        req = hook(req).await?;
+    }
+    Ok(req)
+}
+
+// App hooks first, then outer blueprints before nested ones, each group in
+// registration order
+pub(crate) fn applicable<H: Clone>(hooks: &[Scoped<H>], blueprint: Option<&str>) -> Vec<H> {
+    let mut matching: Vec<(usize, H)> = hooks
+        .iter()
+        .filter_map(|scoped| match (&scoped.blueprint, blueprint) {
+            (None, _) => Some((0, scoped.hook.clone())),
+            (Some(scope), Some(name)) if in_blueprint(scope, name) => {
+                Some((scope.matches('.').count() + 1, scoped.hook.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    matching.sort_by_key(|(depth, _)| *depth);
+    matching.into_iter().map(|(_, hook)| hook).collect()
+}
+
+// "api" covers "api" and "api.users", but not "apiv2"
+fn in_blueprint(scope: &str, name: &str) -> bool {
+    name == scope || name.strip_prefix(scope).is_some_and(|rest| rest.starts_with('.'))
+}
+
+// A body-less copy of the request head for after/teardown hooks
+pub(crate) fn request_head<B>(req: &Request<B>) -> Request<()> {
+    let mut head = Request::new(());
+    *head.method_mut() = req.method().clone();
+    *head.uri_mut() = req.uri().clone();
+    *head.version_mut() = req.version();
+    *head.headers_mut() = req.headers().clone();
+
+    let extensions = req.extensions();
+    let copied = head.extensions_mut();
+    if let Some(g) = extensions.get::<G>() {
+        copied.insert(g.clone());
+    }
+    if let Some(state) = extensions.get::<Arc<StateMap>>() {
+        copied.insert(Arc::clone(state));
+    }
+    if let Some(matched) = extensions.get::<MatchedRoute>() {
+        copied.insert(matched.clone());
+    }
+    if let Some(params) = extensions.get::<RouteParams>() {
+        copied.insert(params.clone());
+    }
+    head
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+    use crate::testing::{get, send};
+    use crate::{abort, Blueprint, FlaskApp, IntoResponse};
+
+    #[test]
+    fn test_in_blueprint() {
+        assert!(in_blueprint("api", "api"));
+        assert!(in_blueprint("api", "api.users"));
+        assert!(!in_blueprint("api", "apiv2"));
+        assert!(!in_blueprint("api.users", "api"));
+    }
+
+    #[test]
+    fn test_applicable_hooks_run_app_then_outer_then_inner() {
+        let scoped = |blueprint: Option<&str>, hook: &'static str| Scoped {
+            blueprint: blueprint.map(Arc::from),
+            hook,
+        };
+        let hooks = vec![
+            scoped(Some("api.users"), "users"),
+            scoped(None, "app1"),
+            scoped(Some("api"), "api"),
+            scoped(Some("admin"), "admin"),
+            scoped(None, "app2"),
+        ];
+
+        assert_eq!(applicable(&hooks, None), vec!["app1", "app2"]);
+        assert_eq!(applicable(&hooks, Some("api")), vec!["app1", "app2", "api"]);
+        assert_eq!(applicable(&hooks, Some("api.users")), vec!["app1", "app2", "api", "users"]);
+    }
+
+    #[tokio::test]
+    async fn test_request_hooks() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+        let app = FlaskApp::new();
+
+        let l = Arc::clone(&log);
+        app.before_request(move |req| {
+            l.lock().unwrap().push(format!("before {}", req.uri().path()));
+            async move {
+                if req.headers().contains_key("x-block") {
+                    Err(StatusCode::FORBIDDEN.into_response())
+                } else {
+                    Ok(req)
+                }
+            }
+        })
+        .await;
+        app.after_request(|req, mut response| async move {
+            let path = req.uri().path().to_string();
+            response.headers_mut().insert("x-path", path.parse().unwrap());
+            response
+        })
+        .await;
+        let l = Arc::clone(&log);
+        app.teardown_request(move |req, error| {
+            l.lock().unwrap().push(format!("teardown {} {:?}", req.uri().path(), error));
+            async {}
+        })
+        .await;
+
+        let mut admin = Blueprint::new("admin");
+        let l = Arc::clone(&log);
+        admin.before_request(move |req| {
+            l.lock().unwrap().push("before admin".to_string());
+            async move { Ok(req) }
+        });
+        admin.get("/", |_req, _params| "admin");
+        admin.get("/boom", |_req, _params| -> &'static str { panic!("boom") });
+        app.register_blueprint(admin, "/admin").await;
+        app.get("/", |_req, _params| "home").await;
+        app.get("/fail", |_req, _params| Err::<&str, _>(abort(StatusCode::SERVICE_UNAVAILABLE))).await;
+
+        assert_eq!(get(&app, "/").await.header("x-path"), Some("/"));
+        let blocked = Request::builder().uri("/").header("x-block", "1").body(Body::empty()).unwrap();
+        let response = send(&app, blocked).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.header("x-path"), Some("/"));
+        let response = get(&app, "/missing").await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(response.header("x-path"), Some("/missing"));
+        assert_eq!(get(&app, "/admin/").await.body, "admin");
+
+        // A panicking handler answers 500 and teardown still runs
+        let response = get(&app, "/admin/boom").await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.body, "Internal Server Error");
+
+        // So does an error the handler returns
+        assert_eq!(get(&app, "/fail").await.status, StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "before /",
+                "teardown / None",
+                "before /",
+                "teardown / None",
+                "before /missing",
+                "teardown /missing Some(\"404 Not Found\")",
+                "before /admin/",
+                "before admin",
+                "teardown /admin/ None",
+                "before /admin/boom",
+                "before admin",
+                "teardown /admin/boom Some(\"boom\")",
+                "before /fail",
+                "teardown /fail Some(\"503 Service Unavailable\")",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_panicking_teardown_hook() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = FlaskApp::new();
+        app.get("/", |_req, _params| "home").await;
+        let teardown_log = Arc::clone(&log);
+        app.teardown_request(move |_req, _error| {
+            let log = Arc::clone(&teardown_log);
+            async move { log.lock().unwrap().push("ran") }
+        })
+        .await;
+        app.teardown_request(|_req, _error| async { panic!("teardown failed") }).await;
+
+        assert_eq!(get(&app, "/").await.status, StatusCode::OK);
+        assert_eq!(*log.lock().unwrap(), ["ran"]);
+    }
+
+    #[tokio::test]
+    async fn test_teardown_runs_when_a_request_is_cancelled() {
+        let app = FlaskApp::new().layer(tower::timeout::TimeoutLayer::new(std::time::Duration::from_millis(20)));
+        app.get("/slow", |_req, _params| async {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            "too late"
+        })
+        .await;
+        let (sender, mut errors) = tokio::sync::mpsc::unbounded_channel();
+        app.teardown_request(move |_req, error| {
+            let sender = sender.clone();
+            async move {
+                let _ = sender.send(error);
+            }
+        })
+        .await;
+
+        assert_eq!(get(&app, "/slow").await.status, StatusCode::REQUEST_TIMEOUT);
+        let error = tokio::time::timeout(std::time::Duration::from_secs(5), errors.recv()).await.unwrap();
+        assert_eq!(error.unwrap().as_deref(), Some("request cancelled"));
+    }
+}
@@ -0,0 +1,289 @@
+// JSON request bodies, Flask's `request.get_json()`.
+//
+// `Json<T>` accepts `application/json` and `application/*+json` bodies and
+// deserializes them into `T`. Every failure is a `JsonError` saying what went
+// wrong and where, rendered as an RFC 7807 `application/problem+json` body:
+//
+//   {"type": "about:blank", "title": "Unprocessable Entity", "status": 422,
+//    "detail": "invalid type: string \"ten\", expected u32",
+//    "path": "items[0].qty", "line": 1, "column": 24}
+use std::fmt;
+use std::str;
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+
+use crate::body::{self, BodyError};
+use crate::IntoResponse;
+
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> Json<T> {
+    pub async fn from_request(req: &mut Request<Body>) -> Result<Self, JsonError> {
+        if !is_json(req.headers()) {
+            let content_type = req.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+            return Err(JsonError::UnsupportedMediaType {
+                content_type: content_type.map(str::to_string),
+            });
+        }
+
+        let body = body::to_bytes(std::mem::take(req.body_mut())).await?;
+        Self::from_bytes(&body)
+    }
+
+    // Deserializes an already buffered body; no Content-Type check
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, JsonError> {
+        if let Err(e) = str::from_utf8(bytes) {
+            return Err(JsonError::InvalidUtf8 {
+                valid_up_to: e.valid_up_to(),
+            });
+        }
+
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+            let path = e.path().to_string();
+            JsonError::from_serde(e.into_inner(), (path != ".").then_some(path))
+        })?;
+        // Trailing characters after the value
+        deserializer.end().map_err(|e| JsonError::from_serde(e, None))?;
+        Ok(Json(value))
+    }
+}
+
+// `application/json` or a structured syntax suffix such as
+// `application/vnd.api+json`; parameters like `charset` are ignored
+fn is_json(headers: &HeaderMap) -> bool {
+    let media_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|media_type| media_type.trim().to_ascii_lowercase());
+    match media_type.as_deref().and_then(|media_type| media_type.split_once('/')) {
+        Some(("application", subtype)) => subtype == "json" || subtype.ends_with("+json"),
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+    // Missing or non-JSON Content-Type (415)
+    UnsupportedMediaType { content_type: Option<String> },
+    // Reading the body failed or hit the route's size limit (400/413)
+    Body(BodyError),
+    // The body is not UTF-8 (400)
+    InvalidUtf8 { valid_up_to: usize },
+    // Not JSON at all, or cut short (400)
+    Syntax { message: String, line: usize, column: usize },
+    // Valid JSON that does not fit the target type (422); `path` locates the
+    // offending value, e.g. "items[0].qty"
+    Data {
+        message: String,
+        path: Option<String>,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl JsonError {
+    fn from_serde(error: serde_json::Error, path: Option<String>) -> Self {
+        // serde_json appends " at line L column C" to its messages
+        let message = error.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) => message[..index].to_string(),
+            None => message,
+        };
+        let (line, column) = (error.line(), error.column());
+        match error.classify() {
+            Category::Data => JsonError::Data {
+                message,
+                path,
+                line,
+                column,
+            },
+            Category::Syntax | Category::Eof | Category::Io => JsonError::Syntax { message, line, column },
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            JsonError::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonError::Body(e) => e.status(),
+            JsonError::InvalidUtf8 { .. } | JsonError::Syntax { .. } => StatusCode::BAD_REQUEST,
+            JsonError::Data { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    // Where in the body parsing failed, 1-based
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            JsonError::Syntax { line, .. } | JsonError::Data { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            JsonError::Syntax { column, .. } | JsonError::Data { column, .. } => Some(*column),
+            _ => None,
+        }
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            JsonError::Data { path, .. } => path.as_deref(),
+            _ => None,
+        }
+    }
+
+    // The human readable part of the message, without location
+    fn detail(&self) -> String {
+        match self {
+            JsonError::Syntax { message, .. } | JsonError::Data { message, .. } => message.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::UnsupportedMediaType { .. } => {
+                write!(f, "Expected request with `Content-Type: application/json`")
+            }
+            JsonError::Body(e) => write!(f, "{}", e),
+            JsonError::InvalidUtf8 { valid_up_to } => {
+                write!(f, "Body is not valid UTF-8 (invalid byte at offset {})", valid_up_to)
+            }
+            JsonError::Syntax { message, line, column } => {
+                write!(f, "Invalid JSON at line {}, column {}: {}", line, column, message)
+            }
+            JsonError::Data {
+                message,
+                path: Some(path),
+                line,
+                column,
+            } => write!(f, "Invalid value for `{}` at line {}, column {}: {}", path, line, column, message),
+            JsonError::Data {
+                message,
+                path: None,
+                line,
+                column,
+            } => write!(f, "Invalid JSON value at line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JsonError::Body(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BodyError> for JsonError {
+    fn from(error: BodyError) -> Self {
+        JsonError::Body(error)
+    }
+}
+
+// RFC 7807 problem details; location fields are extension members
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        let mut problem = serde_json::json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or(""),
+            "status": status.as_u16(),
+            "detail": self.detail(),
+        });
+        if let Some(path) = self.path() {
+            problem["path"] = path.into();
+        }
+        if let (Some(line), Some(column)) = (self.line(), self.column()) {
+            problem["line"] = line.into();
+            problem["column"] = column.into();
+        }
+
+        let mut response = Response::new(Body::from(problem.to_string()));
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/problem+json"));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Order {
+        #[allow(dead_code)]
+        items: Vec<Item>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Item {
+        #[allow(dead_code)]
+        qty: u32,
+    }
+
+    fn request(content_type: Option<&str>, body: &'static str) -> Request<Body> {
+        let mut req = Request::builder();
+        if let Some(content_type) = content_type {
+            req = req.header(CONTENT_TYPE, content_type);
+        }
+        req.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_content_type_is_checked() {
+        for content_type in ["application/json", "application/json; charset=utf-8", "application/vnd.api+json"] {
+            let mut req = request(Some(content_type), r#"{"items": []}"#);
+            assert!(Json::<Order>::from_request(&mut req).await.is_ok(), "{}", content_type);
+        }
+        for content_type in [None, Some("text/plain"), Some("application/jsonp")] {
+            let mut req = request(content_type, r#"{"items": []}"#);
+            let err = Json::<Order>::from_request(&mut req).await.unwrap_err();
+            assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE, "{:?}", content_type);
+        }
+    }
+
+    #[test]
+    fn test_errors_carry_their_location() {
+        let err = Json::<Order>::from_bytes(b"{\"items\": [\n  {\"qty\": 1},\n  {\"qty\": \"ten\"}]}").unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(err.path(), Some("items[1].qty"));
+        assert_eq!((err.line(), err.column()), (Some(3), Some(15)));
+
+        let err = Json::<Order>::from_bytes(b"{\"items\": [}").unwrap_err();
+        assert!(matches!(err, JsonError::Syntax { line: 1, column: 12, .. }), "{:?}", err);
+        let err = Json::<Order>::from_bytes(b"{\"items\": []} trailing").unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        let err = Json::<Order>::from_bytes(b"{\"items\": \xff}").unwrap_err();
+        assert!(matches!(err, JsonError::InvalidUtf8 { valid_up_to: 10 }));
+    }
+
+    #[tokio::test]
+    async fn test_problem_json_response() {
+        let err = Json::<Order>::from_bytes(br#"{"items": 3}"#).unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["title"], "Unprocessable Entity");
+        assert_eq!(problem["status"], 422);
+        assert_eq!(problem["detail"], "invalid type: integer `3`, expected a sequence");
+        assert_eq!(problem["path"], "items");
+        assert_eq!(problem["column"], 11);
+    }
+}
@@ -1,60 +1,126 @@
-use std::collections::HashMap;
+pub mod blueprint;
+pub mod body;
+pub mod context;
+pub mod converters;
+mod debug_page;
+pub mod error;
+mod errorhandlers;
+pub mod extract;
+pub mod handler;
+pub mod json;
+mod hooks;
+pub mod middleware;
+pub mod multipart;
+mod panic;
+pub mod path;
+pub mod query;
+pub mod response;
+pub mod router;
+pub mod rule;
+pub mod server;
+pub mod state;
+pub mod static_files;
+#[cfg(test)]
+mod testing;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use serde::Deserialize;
+use hyper::service::make_service_fn;
+use hyper::header::{ALLOW, LOCATION};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use tower::util::BoxCloneSyncService;
+use tower::{BoxError, Layer, Service};
 use std::fmt;
 use std::str;
+use std::str::FromStr;
 
-#[derive(Debug)]
-pub struct JsonError {
-    msg: String,
-}
+pub use blueprint::Blueprint;
+pub use body::{BodyError, BodyStream, LengthLimitError};
+use blueprint::Mounted;
+pub use context::{RequestExt, G};
+pub use error::{abort, HttpError};
+pub use extract::{Form, FromRequest, FromRequestParts, Query};
+pub use handler::{handler, Handler};
+pub use json::{Json, JsonError};
+pub use middleware::{from_fn, Middleware, Next};
+pub use multipart::{Multipart, MultipartError, MultipartLimits, UploadedFile};
+pub use path::{Path, PathError};
+pub use query::{Args, QueryError};
+pub use response::IntoResponse;
+pub use router::UrlBuildError;
+pub use rule::Rule;
+pub use server::ServeError;
+pub use state::State;
+pub use static_files::send_from_directory;
 
-impl fmt::Display for JsonError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "JSON Error: {}", self.msg)
-    }
-}
+use converters::Converter;
+use handler::{HandlerOutput, RouteHandler};
+use errorhandlers::ErrorHandlers;
+use hooks::Hooks;
+use panic::CatchUnwind;
+use middleware::{BoxedService, DynLayer, DynMiddleware, Endpoint};
+use router::{Lookup, Match, Router};
+use state::StateMap;
 
-impl JsonError {
-    pub fn new(msg: &str) -> Self {
-        JsonError {
-            msg: msg.to_string(),
-        }
-    }
+pub struct FlaskApp {
+    inner: Arc<AppInner>,
+    // Wrapped around the app's service when the server starts
+    layers: Vec<DynLayer>,
 }
 
-#[derive(Debug)]
-pub struct Json<T>(pub T);
-
-impl<T: for<'de> Deserialize<'de>> Json<T> {
-    pub async fn from_request(req: &mut Request<Body>) -> Result<Self, JsonError> {
-        let body_bytes = hyper::body::to_bytes(req.body_mut()).await
-            .map_err(|_| JsonError::new("Failed to read body"))?;
-        
-        let body_str = str::from_utf8(&body_bytes)
-            .map_err(|_| JsonError::new("Body is not valid UTF-8"))?;
-
-        serde_json::from_str(body_str)
-            .map(Json)
-            .map_err(|e| JsonError::new(&format!("Failed to parse JSON: {}", e)))
-    }
+// Everything the server needs to answer a request, shared by all connections
+struct AppInner {
+    routes: RwLock<Router<RouteEntry>>,
+    blueprints: RwLock<HashSet<String>>,
+    hooks: RwLock<Hooks>,
+    error_handlers: RwLock<ErrorHandlers>,
+    middleware: Arc<[DynMiddleware]>,
+    state: Arc<StateMap>,
+    config: FlaskConfig,
 }
 
-pub struct FlaskApp {
-    routes: Arc<RwLock<HashMap<String, RouteHandler>>>,
-    config: FlaskConfig,
+// What the router stores for each method/pattern pair
+#[derive(Clone)]
+pub(crate) struct RouteEntry {
+    handler: RouteHandler,
+    endpoint: Option<Arc<str>>,
+    blueprint: Option<Arc<str>>,
+    // Some(None): unlimited despite the app's limit
+    max_content_length: Option<Option<u64>>,
+    strict_slashes: Option<bool>,
 }
 
-type RouteHandler = Arc<dyn Fn(Request<Body>, RouteParams) -> Response<Body> + Send + Sync>;
+// The route that is serving the current request, available from the request
+// extensions (Flask's `request.url_rule` / `request.blueprint`)
+#[derive(Debug, Clone, Default)]
+pub struct MatchedRoute {
+    pub pattern: String,
+    pub endpoint: Option<String>,
+    pub blueprint: Option<String>,
+}
 
 #[derive(Debug, Clone)]
 pub struct FlaskConfig {
     pub debug: bool,
     pub host: String,
     pub port: u16,
+    // Largest request body any route accepts (Flask's MAX_CONTENT_LENGTH);
+    // None means unlimited. `Rule::max_content_length` overrides it.
+    pub max_content_length: Option<u64>,
+    // Answer requests for a non-canonical path such as "/users//7" with a
+    // 308 to "/users/7" instead of serving them directly
+    pub canonical_redirect: bool,
+    // Werkzeug's strict_slashes: "/docs" redirects (308) to a route defined
+    // as "/docs/". When false, routes match with or without the trailing
+    // slash. `Rule::strict_slashes` overrides it.
+    pub strict_slashes: bool,
+    // Size limits for the `Multipart` extractor
+    pub multipart: MultipartLimits,
 }
 
 impl Default for FlaskConfig {
@@ -63,6 +129,10 @@ impl Default for FlaskConfig {
             debug: false,
             host: "127.0.0.1".to_string(),
             port: 8080,
+            max_content_length: None,
+            canonical_redirect: false,
+            strict_slashes: true,
+            multipart: MultipartLimits::default(),
         }
     }
 }
@@ -70,12 +140,15 @@ impl Default for FlaskConfig {
 #[derive(Debug, Clone)]
 pub struct RouteParams {
     pub params: HashMap<String, String>,
+    // Names in the order they were captured, for positional access
+    order: Vec<String>,
 }
 
 impl RouteParams {
     pub fn new() -> Self {
         RouteParams {
             params: HashMap::new(),
+            order: Vec::new(),
         }
     }
 
@@ -84,8 +157,37 @@ impl RouteParams {
     }
 
     pub fn insert(&mut self, key: String, value: String) {
+        if !self.params.contains_key(&key) {
+            self.order.push(key.clone());
+        }
         self.params.insert(key, value);
     }
+
+    // Parses a single parameter, e.g. `params.parse::<u64>("id")`
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<T, PathError> {
+        let value = self.get(key).ok_or_else(|| PathError::Missing(key.to_string()))?;
+        path::parse_param(key, value)
+    }
+
+    // Iterates in capture order; entries added directly to `params` come last
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        let ordered = self
+            .order
+            .iter()
+            .filter_map(|key| self.params.get_key_value(key));
+        let unordered = self.params.iter().filter(|(key, _)| !self.order.contains(key));
+        ordered
+            .chain(unordered)
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
 }
 
 impl fmt::Display for RouteParams {
@@ -102,191 +204,575 @@ impl Default for RouteParams {
 
 impl FlaskApp {
     pub fn new() -> Self {
-        FlaskApp {
-            routes: Arc::new(RwLock::new(HashMap::new())),
-            config: FlaskConfig::default(),
-        }
+        Self::with_config(FlaskConfig::default())
     }
 
     pub fn with_config(config: FlaskConfig) -> Self {
         FlaskApp {
-            routes: Arc::new(RwLock::new(HashMap::new())),
-            config,
+            inner: Arc::new(AppInner {
+                routes: RwLock::new(Router::new()),
+                blueprints: RwLock::new(HashSet::new()),
+                hooks: RwLock::new(Hooks::default()),
+                error_handlers: RwLock::new(ErrorHandlers::default()),
+                middleware: Arc::from(Vec::new()),
+                state: Arc::new(StateMap::default()),
+                config,
+            }),
+            layers: Vec::new(),
         }
     }
 
-    pub async fn route<F>(&self, path: &str, handler: F)
+    // Shares `state` with every handler through the `State<S>` extractor or
+    // `req.state::<S>()`. One value per type; call again for other types.
+    pub fn with_state<S: Send + Sync + 'static>(mut self, state: S) -> Self {
+        Arc::get_mut(&mut self.inner_mut().state)
+            .expect("with_state is called before the app is shared")
+            .insert(state);
+        self
+    }
+
+    // Global middleware around routing, hooks and handlers; see the
+    // `middleware` module. The first one added sees the request first.
+    pub fn middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        let inner = self.inner_mut();
+        let mut chain = inner.middleware.to_vec();
+        chain.push(middleware::erase(middleware));
+        inner.middleware = Arc::from(chain);
+        self
+    }
+
+    // Wraps the whole app in a tower layer (outside any middleware). The
+    // first layer added is the outermost.
+    pub fn layer<L>(mut self, layer: L) -> Self
     where
-        F: Fn(Request<Body>, RouteParams) -> Response<Body> + Send + Sync + 'static,
+        L: Layer<BoxedService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
     {
-        let mut routes = self.routes.write().await;
-        routes.insert(path.to_string(), Arc::new(handler));
+        self.layers.push(middleware::erase_layer(layer));
+        self
+    }
+
+    fn inner_mut(&mut self) -> &mut AppInner {
+        Arc::get_mut(&mut self.inner).expect("the app is configured before it is shared")
     }
 
-    pub async fn get<F>(&self, path: &str, handler: F)
+    pub async fn route<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
     where
-        F: Fn(Request<Body>, RouteParams) -> Response<Body> + Send + Sync + 'static,
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
     {
-        let full_path = format!("GET {}", path);
-        self.route(&full_path, move |req, params| {
-            if req.method() == Method::GET {
-                handler(req, params)
-            } else {
-                Response::builder()
-                    .status(StatusCode::METHOD_NOT_ALLOWED)
-                    .body(Body::from("Method not allowed"))
-                    .unwrap()
-            }
-        }).await;
+        self.add_route(None, rule.into(), handler::boxed(handler)).await;
     }
 
-    pub async fn post<F>(&self, path: &str, handler: F)
+    pub async fn get<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
     where
-        F: Fn(Request<Body>, RouteParams) -> Response<Body> + Send + Sync + 'static,
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
     {
-        let full_path = format!("POST {}", path);
-        self.route(&full_path, move |req, params| {
-            if req.method() == Method::POST {
-                handler(req, params)
-            } else {
-                Response::builder()
-                    .status(StatusCode::METHOD_NOT_ALLOWED)
-                    .body(Body::from("Method not allowed"))
-                    .unwrap()
+        self.add_route(Some(Method::GET), rule.into(), handler::boxed(handler)).await;
+    }
+
+    pub async fn post<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::POST), rule.into(), handler::boxed(handler)).await;
+    }
+
+    pub async fn put<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::PUT), rule.into(), handler::boxed(handler)).await;
+    }
+
+    pub async fn delete<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::DELETE), rule.into(), handler::boxed(handler)).await;
+    }
+
+    pub async fn patch<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::PATCH), rule.into(), handler::boxed(handler)).await;
+    }
+
+    pub async fn head<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::HEAD), rule.into(), handler::boxed(handler)).await;
+    }
+
+    pub async fn options<F, R, M>(&self, rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        self.add_route(Some(Method::OPTIONS), rule.into(), handler::boxed(handler)).await;
+    }
+
+    // Flask's `methods=[...]`: one handler registered for several methods
+    pub async fn route_methods<F, R, M>(&self, methods: &[Method], rule: impl Into<Rule>, handler: F)
+    where
+        F: Fn(Request<Body>, RouteParams) -> R + Send + Sync + 'static,
+        R: HandlerOutput<M>,
+    {
+        let rule = rule.into();
+        let handler = handler::boxed(handler);
+        for method in methods {
+            self.add_route(Some(method.clone()), rule.clone(), Arc::clone(&handler)).await;
+        }
+    }
+
+    // Makes `{name:<converter name>}` usable in routes registered afterwards
+    pub async fn register_converter<C: Converter>(&self, name: &str, converter: C) {
+        self.inner.routes.write().await.register_converter(name, converter);
+    }
+
+    // Mounts every route of `blueprint` (and its nested blueprints) below
+    // `url_prefix`. Blueprint names must be unique within the app.
+    pub async fn register_blueprint(&self, blueprint: Blueprint, url_prefix: &str) {
+        let mut mounted = Mounted::default();
+        blueprint.mount(url_prefix, None, &[], &mut mounted);
+
+        let mut registered = self.inner.blueprints.write().await;
+        for name in mounted.names {
+            if !registered.insert(name.clone()) {
+                panic!("A blueprint named {:?} is already registered", name);
             }
-        }).await;
+        }
+
+        self.inner.hooks.write().await.extend(mounted.hooks);
+        self.inner.error_handlers.write().await.extend(mounted.error_handlers);
+
+        let mut routes = self.inner.routes.write().await;
+        for route in mounted.routes {
+            let entry = RouteEntry {
+                handler: route.handler,
+                endpoint: route.endpoint.as_deref().map(Arc::from),
+                blueprint: Some(Arc::from(route.blueprint)),
+                max_content_length: route.max_content_length,
+                strict_slashes: route.strict_slashes,
+            };
+            Self::insert_route(&mut routes, route.method, &route.path, entry);
+        }
     }
 
+    // Runs before every request's handler. Returning `Err(response)` skips the
+    // handler (and any later before hooks) and sends that response instead:
+    //
+    //   app.before_request(|req| async move {
+    //       if req.headers().contains_key("x-api-key") { Ok(req) }
+    //       else { Err(StatusCode::UNAUTHORIZED.into_response()) }
+    //   }).await;
+    pub async fn before_request<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Request<Body>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Request<Body>, Response<Body>>> + Send + 'static,
+    {
+        self.inner.hooks.write().await.add_before(hooks::before(hook));
+    }
+
+    // Runs on every outgoing response, including 404s and responses from
+    // before hooks; gets a copy of the request head
+    pub async fn after_request<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Request<()>, Response<Body>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.inner.hooks.write().await.add_after(hooks::after(hook));
+    }
+
+    // Runs at the very end of every request. The error is the panic message
+    // when a hook or the handler panicked, or a description such as
+    // "404 Not Found" when the response is an error raised by the framework,
+    // a handler's `Err` or `None`; a response a handler builds itself, even
+    // with an error status, is not an error.
+    pub async fn teardown_request<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Request<()>, Option<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.inner.hooks.write().await.add_teardown(hooks::teardown(hook));
+    }
+
+    // Flask's `errorhandler(404)`: replaces error responses with `status`,
+    // such as unknown paths, 405s and extractor failures. The handler gets
+    // the request head and the original response; return the status with
+    // the body, e.g. `(StatusCode::NOT_FOUND, page)`.
+    pub async fn errorhandler<F, Fut>(&self, status: StatusCode, handler: F)
+    where
+        F: Fn(Request<()>, Response<Body>) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.inner.error_handlers.write().await.add_status(status, errorhandlers::status(handler));
+    }
+
+    // Flask's `errorhandler(MyError)`: renders every `Err(MyError)` returned
+    // by a handler, and extractor rejections of that type, e.g.
+    // `app.errorhandler_for(|e: JsonError| ...)`
+    pub async fn errorhandler_for<E, F, R>(&self, handler: F)
+    where
+        E: 'static,
+        F: Fn(E) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.inner.error_handlers.write().await.add_type(errorhandlers::typed(handler));
+    }
+
+    async fn add_route(&self, method: Option<Method>, rule: Rule, handler: RouteHandler) {
+        let entry = RouteEntry {
+            handler: middleware::wrap_route(&rule.wraps, handler),
+            endpoint: rule.endpoint.as_deref().map(Arc::from),
+            blueprint: None,
+            max_content_length: rule.max_content_length,
+            strict_slashes: rule.strict_slashes,
+        };
+        Self::insert_route(&mut *self.inner.routes.write().await, method, &rule.path, entry);
+    }
+
+    fn insert_route(routes: &mut Router<RouteEntry>, method: Option<Method>, path: &str, entry: RouteEntry) {
+        let result = match entry.endpoint.as_deref() {
+            Some(endpoint) => routes.name(endpoint, path),
+            None => Ok(()),
+        };
+        if let Err(e) = result.and_then(|_| routes.insert(method, path, entry)) {
+            panic!("{}", e);
+        }
+    }
+
+    // Flask's `url_for`: builds the URL of a named endpoint. Values that fill
+    // no `{param}` of the rule are appended as a query string.
+    //
+    //   app.url_for("user_posts", [("id", "7"), ("page", "2")]).await
+    //   // Ok("/users/7/posts?page=2")
+    pub async fn url_for<I, K, V>(&self, endpoint: &str, values: I) -> Result<String, UrlBuildError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: ToString,
+    {
+        let values: Vec<(String, String)> = values
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_string(), value.to_string()))
+            .collect();
+        self.inner.routes.read().await.url_for(endpoint, &values)
+    }
+
+    // Serves on an IPv4 address, logging instead of returning errors; see
+    // `run_with_config` and `serve`
     pub async fn run(&self, addr: [u8; 4], port: u16) {
-        let routes = Arc::clone(&self.routes);
-        
+        let addr = SocketAddr::from((addr, port));
+        let result = match std::net::TcpListener::bind(addr) {
+            Ok(listener) => self.serve(listener).await,
+            Err(source) => Err(ServeError::Bind { addr, source }),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    // Binds `FlaskConfig::host` and `port` and serves until the server fails.
+    // The host may be an IPv4 or IPv6 address ("::" or "[::1]") or a hostname.
+    pub async fn run_with_config(&self) -> Result<(), ServeError> {
+        let listener = server::bind(&self.inner.config.host, self.inner.config.port).await?;
+        self.serve(listener).await
+    }
+
+    // Serves on an already bound listener, e.g. one bound to port 0 or
+    // inherited from a process manager
+    pub async fn serve(&self, listener: std::net::TcpListener) -> Result<(), ServeError> {
+        let addr = listener.local_addr().map_err(ServeError::Listener)?;
+        let service = self.service();
         let make_svc = make_service_fn(move |_conn| {
-            let routes = Arc::clone(&routes);
-            async move {
-                Ok::<_, hyper::Error>(service_fn(move |req| {
-                    let routes = Arc::clone(&routes);
-                    async move {
-                        Self::handle_request(req, routes).await
-                    }
-                }))
-            }
+            let service = service.clone();
+            async move { Ok::<_, Infallible>(service) }
         });
+        let server = Server::from_tcp(listener).map_err(ServeError::Server)?.serve(make_svc);
 
-        let addr_str = format!("{}.{}.{}.{}:{}", addr[0], addr[1], addr[2], addr[3], port);
-        let addr = addr_str.parse().unwrap();
-
-        let server = Server::bind(&addr).serve(make_svc);
-        
         println!("Running on http://{}", addr);
-        
-        if self.config.debug {
+        if self.inner.config.debug {
             println!("Debug mode enabled");
         }
-        
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
-        }
+        server.await.map_err(ServeError::Server)
     }
 
     pub fn config(&self) -> &FlaskConfig {
-        &self.config
-    }
-
-    async fn handle_request(
-        req: Request<Body>,
-        routes: Arc<RwLock<HashMap<String, RouteHandler>>>,
-    ) -> Result<Response<Body>, hyper::Error> {
-        let routes = routes.read().await;
-        let method = req.method();
-        let path = req.uri().path();
-        
-        // Try exact method + path match first
-        let method_path = format!("{} {}", method, path);
-        if let Some(handler) = routes.get(&method_path) {
-            let params = Self::extract_path_params(path, path);
-            return Ok(handler(req, params));
-        }
-        
-        // Try path-only match for generic routes
-        if let Some(handler) = routes.get(path) {
-            let params = Self::extract_path_params(path, path);
-            return Ok(handler(req, params));
+        &self.inner.config
+    }
+
+    // The app as a tower service, with every layer applied
+    fn service(&self) -> BoxedService {
+        let inner = Arc::clone(&self.inner);
+        let service = tower::service_fn(move |req| Arc::clone(&inner).handle_request(req));
+        middleware::apply_layers(&self.layers, BoxCloneSyncService::new(service))
+    }
+
+    #[cfg(test)]
+    async fn handle_request(&self, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        tower::ServiceExt::oneshot(self.service(), req).await
+    }
+}
+
+impl AppInner {
+    async fn handle_request(self: Arc<Self>, mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        req.extensions_mut().insert(G::new());
+        req.extensions_mut().insert(Arc::clone(&self.state));
+        req.extensions_mut().insert(self.config.multipart);
+        // The app's body limit also holds for middleware; a declared
+        // Content-Length is only rejected once the route's limit is known
+        body::limit_body(&mut req, self.config.max_content_length);
+
+        if self.middleware.is_empty() {
+            return Ok(self.dispatch(req).await);
         }
-        
-        // Handle route parameters (e.g., /users/{id})
-        for (pattern, handler) in routes.iter() {
-            if let Some(params) = Self::match_route_pattern(pattern, path, method) {
-                return Ok(handler(req, params));
+        // `dispatch` catches its own panics; this covers the middleware, whose
+        // panics go to the app-wide handlers as the route's would
+        let (method, uri) = (req.method().clone(), req.uri().clone());
+        let errors = self.error_handlers.read().await.for_request(None);
+        let debug = self.config.debug;
+        let head = (debug || !errors.is_empty()).then(|| hooks::request_head(&req));
+        let chain = Arc::clone(&self.middleware);
+        let endpoint: Endpoint = Arc::new(move |req| {
+            let inner = Arc::clone(&self);
+            Box::pin(async move { inner.dispatch(req).await })
+        });
+        let outcome = CatchUnwind::new(Box::pin(Next::new(chain, endpoint).run(req)))
+            .force_backtrace(debug)
+            .await;
+        let response = match outcome {
+            Ok(response) => response,
+            Err(panic) => {
+                let response = panic::internal_error(&method, &uri, &panic);
+                match &head {
+                    Some(head) if !debug => {
+                        let head = hooks::request_head(head);
+                        CatchUnwind::new(Box::pin(async move { errors.handle(&head, response).await }))
+                            .await
+                            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+                    }
+                    _ => response,
+                }
             }
-        }
-        
-        Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::from("Not Found"))
-            .unwrap())
-    }
-
-    fn match_route_pattern(pattern: &str, path: &str, method: &Method) -> Option<RouteParams> {
-        // Handle method-specific patterns
-        let actual_pattern = if pattern.starts_with("GET ") 
-            || pattern.starts_with("POST ") 
-            || pattern.starts_with("PUT ") 
-            || pattern.starts_with("DELETE ") {
-            &pattern[method.as_str().len() + 1..]
+        };
+        Ok(match head {
+            Some(head) if debug => debug_page::render(&head, response).await,
+            _ => response,
+        })
+    }
+
+    // Werkzeug's strict_slashes, for a path nothing matched: "/docs" for a
+    // route "/docs/" redirects there when the route is strict; without
+    // strictness either form is served by the route
+    fn other_slash(&self, routes: &Router<RouteEntry>, req: &mut Request<Body>) -> Dispatch {
+        let path = router::normalize_path(req.uri().path());
+        let other = match path.strip_suffix('/') {
+            Some("") => return Dispatch::NotFound,
+            Some(without) => without.to_string(),
+            None => format!("{}/", path),
+        };
+        let Some(m) = routes.at(req.method(), &other) else {
+            return Dispatch::NotFound;
+        };
+
+        if !m.value.strict_slashes.unwrap_or(self.config.strict_slashes) {
+            Dispatch::found(m, req)
+        } else if other.ends_with('/') {
+            Dispatch::Redirect(with_query(other, req.uri()))
         } else {
-            pattern
+            Dispatch::NotFound
+        }
+    }
+
+    // Routing, hooks and the handler
+    async fn dispatch(&self, mut req: Request<Body>) -> Response<Body> {
+        let mut dispatch = {
+            let routes = self.routes.read().await;
+            match routes.lookup(req.method(), req.uri().path()) {
+                Lookup::Found(m) => Dispatch::found(m, &mut req),
+                Lookup::MethodNotAllowed(allowed) => Dispatch::MethodNotAllowed(allowed),
+                Lookup::NotFound => self.other_slash(&routes, &mut req),
+            }
         };
-        
-        let mut params = RouteParams::new();
-        let pattern_parts: Vec<&str> = actual_pattern.split('/').collect();
-        let path_parts: Vec<&str> = path.split('/').collect();
-        
-        if pattern_parts.len() != path_parts.len() {
-            return None;
+
+        if self.config.canonical_redirect && matches!(dispatch, Dispatch::Route(..) | Dispatch::MethodNotAllowed(_)) {
+            if let Cow::Owned(path) = router::normalize_path(req.uri().path()) {
+                dispatch = Dispatch::Redirect(with_query(path, req.uri()));
+            }
         }
-        
-        let mut matches = true;
-        for (i, pattern_part) in pattern_parts.iter().enumerate() {
-            if let Some(path_part) = path_parts.get(i) {
-                if pattern_part.starts_with('{') && pattern_part.ends_with('}') {
-                    let param_name = &pattern_part[1..pattern_part.len() - 1];
-                    params.insert(param_name.to_string(), path_part.to_string());
-                } else if pattern_part != path_part {
-                    matches = false;
-                    break;
-                }
-            } else {
-                matches = false;
-                break;
+
+        let blueprint = match &dispatch {
+            Dispatch::Route(entry, _) => entry.blueprint.clone(),
+            _ => None,
+        };
+
+        // The route's body limit replaces the app's for its own middleware,
+        // hooks and handler
+        if let Dispatch::Route(entry, _) = &dispatch {
+            let limit = entry.max_content_length.unwrap_or(self.config.max_content_length);
+            body::limit_body(&mut req, limit);
+            if let Err(e) = body::check_length(&req, limit) {
+                dispatch = Dispatch::TooLarge(e);
             }
         }
-        
-        if matches { Some(params) } else { None }
-    }
-
-    fn extract_path_params(route_pattern: &str, actual_path: &str) -> RouteParams {
-        let mut params = RouteParams::new();
-        
-        let pattern_parts: Vec<&str> = route_pattern.split('/').collect();
-        let path_parts: Vec<&str> = actual_path.split('/').collect();
-        
-        if pattern_parts.len() != path_parts.len() {
-            return params;
+
+        // The debug page shows the route parameters, which otherwise only
+        // reach the handler
+        let debug = self.config.debug;
+        if let (true, Dispatch::Route(_, params)) = (debug, &dispatch) {
+            req.extensions_mut().insert(params.clone());
         }
-        
-        for (i, pattern_part) in pattern_parts.iter().enumerate() {
-            if let Some(path_part) = path_parts.get(i) {
-                if pattern_part.starts_with('{') && pattern_part.ends_with('}') {
-                    let param_name = &pattern_part[1..pattern_part.len() - 1];
-                    params.insert(param_name.to_string(), path_part.to_string());
+
+        let hooks = self.hooks.read().await.for_request(blueprint.as_deref());
+        let errors = Arc::new(self.error_handlers.read().await.for_request(blueprint.as_deref()));
+        req.extensions_mut().insert(Arc::clone(&errors));
+        let needs_head = debug || !hooks.after.is_empty() || !hooks.teardown.is_empty() || !errors.is_empty();
+        let head = needs_head.then(|| hooks::request_head(&req));
+
+        let (method, uri) = (req.method().clone(), req.uri().clone());
+
+        // Created before the pipeline runs so the teardown hooks also run
+        // when this future is dropped midway
+        let mut teardown = head
+            .as_ref()
+            .filter(|_| !hooks.teardown.is_empty())
+            .map(|head| hooks::Teardown::new(hooks.teardown.clone(), hooks::request_head(head)));
+
+        let pipeline_head = head.as_ref().map(hooks::request_head);
+        let pipeline_errors = Arc::clone(&errors);
+        let pipeline = async move {
+            let mut response = match hooks::run_before(&hooks.before, req).await {
+                Ok(req) => dispatch.respond(req).await,
+                Err(response) => response,
+            };
+            if let Some(head) = pipeline_head {
+                response = pipeline_errors.handle(&head, response).await;
+                for hook in &hooks.after {
+                    response = hook(hooks::request_head(&head), response).await;
                 }
             }
+            response
+        };
+        let outcome = CatchUnwind::new(Box::pin(errorhandlers::scope(Arc::clone(&errors), pipeline)))
+            .force_backtrace(debug)
+            .await;
+
+        // A panic answers 500: the debug page in debug mode, otherwise through
+        // the app's 500 handler if it has one
+        let (mut response, error) = match outcome {
+            Ok(response) => {
+                let error = errorhandlers::describe(&response);
+                (response, error)
+            }
+            Err(panic) => {
+                let response = panic::internal_error(&method, &uri, &panic);
+                let response = match &head {
+                    Some(_) if debug => response,
+                    Some(head) => {
+                        let head = hooks::request_head(head);
+                        CatchUnwind::new(Box::pin(async move { errors.handle(&head, response).await }))
+                            .await
+                            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+                    }
+                    None => response,
+                };
+                (response, Some(panic.message))
+            }
+        };
+
+        if let (true, Some(head)) = (debug, &head) {
+            response = debug_page::render(head, response).await;
         }
-        
-        params
+
+        if let Some(teardown) = &mut teardown {
+            teardown.run(error).await;
+        }
+        response
     }
 }
 
+enum Dispatch {
+    Route(RouteEntry, RouteParams),
+    MethodNotAllowed(Vec<Method>),
+    // Declared Content-Length over the route's limit; the handler never runs
+    TooLarge(LengthLimitError),
+    // 308 to the canonical URL of the route
+    Redirect(String),
+    NotFound,
+}
+
+impl Dispatch {
+    fn found(m: Match<'_, RouteEntry>, req: &mut Request<Body>) -> Self {
+        let matched = MatchedRoute {
+            pattern: m.pattern.to_string(),
+            endpoint: m.value.endpoint.as_deref().map(str::to_string),
+            blueprint: m.value.blueprint.as_deref().map(str::to_string),
+        };
+        req.extensions_mut().insert(matched);
+        Dispatch::Route(m.value.clone(), m.params)
+    }
+
+    async fn respond(self, req: Request<Body>) -> Response<Body> {
+        match self {
+            Dispatch::Route(entry, params) => (entry.handler)(req, params).await,
+            Dispatch::MethodNotAllowed(allowed) => match method_not_allowed(req.method(), allowed) {
+                response if response.status() == StatusCode::OK => response,
+                response => errorhandlers::mark(response),
+            },
+            Dispatch::TooLarge(e) => errorhandlers::mark(BodyError::TooLarge(e).into_response()),
+            Dispatch::Redirect(location) => Response::builder()
+                .status(StatusCode::PERMANENT_REDIRECT)
+                .header(LOCATION, location)
+                .body(Body::from("Permanent Redirect"))
+                .unwrap(),
+            Dispatch::NotFound => errorhandlers::mark(
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::from("Not Found"))
+                    .unwrap(),
+            ),
+        }
+    }
+}
+
+fn with_query(path: String, uri: &Uri) -> String {
+    match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    }
+}
+
+// 405 with an Allow header, or the automatic reply to an OPTIONS request
+fn method_not_allowed(method: &Method, mut allowed: Vec<Method>) -> Response<Body> {
+    allowed.push(Method::OPTIONS);
+    allowed.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    allowed.dedup();
+    let allow = allowed.iter().map(Method::as_str).collect::<Vec<_>>().join(", ");
+
+    let (status, body) = if method == Method::OPTIONS {
+        (StatusCode::OK, "")
+    } else {
+        (StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed")
+    };
+    Response::builder()
+        .status(status)
+        .header(ALLOW, allow)
+        .body(Body::from(body))
+        .unwrap()
+}
+
 impl Default for FlaskApp {
     fn default() -> Self {
         Self::new()
@@ -300,18 +786,16 @@ pub fn text_response(content: &str) -> Response<Body> {
         .unwrap()
 }
 
+// A value that fails to serialize answers 500 instead of panicking
 pub fn json_response<T: serde::ser::Serialize>(data: &T) -> Response<Body> {
-    let json = serde_json::to_string(data).unwrap();
-    Response::builder()
-        .header("Content-Type", "application/json; charset=utf-8")
-        .body(Body::from(json))
-        .unwrap()
+    Json(data).into_response()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use hyper::Method;
+    use testing::{get, send};
 
     #[test]
     fn test_text_response() {
@@ -345,9 +829,7 @@ mod tests {
             .uri("/")
             .body(Body::empty())
             .unwrap();
-
-        let routes = Arc::clone(&app.routes);
-        let response = FlaskApp::handle_request(test_req, routes).await.unwrap();
+        let response = app.handle_request(test_req).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -359,9 +841,7 @@ mod tests {
             .uri("/nonexistent")
             .body(Body::empty())
             .unwrap();
-
-        let routes = Arc::clone(&app.routes);
-        let response = FlaskApp::handle_request(test_req, routes).await.unwrap();
+        let response = app.handle_request(test_req).await.unwrap();
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
@@ -385,9 +865,7 @@ mod tests {
             .uri("/users/123")
             .body(Body::empty())
             .unwrap();
-
-        let routes = Arc::clone(&app.routes);
-        let response = FlaskApp::handle_request(test_req, routes).await.unwrap();
+        let response = app.handle_request(test_req).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -403,9 +881,7 @@ mod tests {
             .uri("/hello")
             .body(Body::empty())
             .unwrap();
-
-        let routes = Arc::clone(&app.routes);
-        let response = FlaskApp::handle_request(test_req, routes).await.unwrap();
+        let response = app.handle_request(test_req).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -421,9 +897,156 @@ mod tests {
             .uri("/data")
             .body(Body::empty())
             .unwrap();
-
-        let routes = Arc::clone(&app.routes);
-        let response = FlaskApp::handle_request(test_req, routes).await.unwrap();
+        let response = app.handle_request(test_req).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
     }
+
+    #[tokio::test]
+    async fn test_method_helpers_and_route_methods() {
+        let app = FlaskApp::new();
+        app.put("/items/{id}", |_req, _params| "put").await;
+        app.delete("/items/{id}", |_req, _params| "delete").await;
+        app.patch("/items/{id}", |_req, _params| "patch").await;
+        app.options("/items/{id}", |_req, _params| "options").await;
+        app.route_methods(&[Method::GET, Method::POST], "/form", |req, _params| {
+            format!("form via {}", req.method())
+        }).await;
+
+        let call = |method: Method, uri: &str| {
+            send(&app, Request::builder().method(method).uri(uri).body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(call(Method::PUT, "/items/1").await.body, "put");
+        assert_eq!(call(Method::DELETE, "/items/1").await.body, "delete");
+        assert_eq!(call(Method::PATCH, "/items/1").await.body, "patch");
+        assert_eq!(call(Method::OPTIONS, "/items/1").await.body, "options");
+        assert_eq!(call(Method::GET, "/form").await.body, "form via GET");
+        assert_eq!(call(Method::POST, "/form").await.body, "form via POST");
+        assert_ne!(call(Method::PUT, "/form").await.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed_head_and_options() {
+        let app = FlaskApp::new();
+        app.get("/x", |_req, _params| "x").await;
+        app.delete("/x", |_req, _params| "deleted").await;
+
+        let call = |method: Method| send(&app, Request::builder().method(method).uri("/x").body(Body::empty()).unwrap());
+
+        let response = call(Method::POST).await;
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.header("Allow"), Some("DELETE, GET, HEAD, OPTIONS"));
+
+        // hyper drops the GET body of a HEAD response but keeps its length
+        let listener = server::bind("127.0.0.1", 0).await.unwrap();
+        let uri = format!("http://{}/x", listener.local_addr().unwrap());
+        let head = Request::builder().method(Method::HEAD).uri(uri).body(Body::empty()).unwrap();
+        tokio::select! {
+            result = app.serve(listener) => panic!("server stopped: {:?}", result),
+            response = hyper::Client::new().request(head) => {
+                let response = response.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(response.headers()[hyper::header::CONTENT_LENGTH], "1");
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                assert!(body.is_empty());
+            }
+        }
+
+        let response = call(Method::OPTIONS).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.header("Allow"), Some("DELETE, GET, HEAD, OPTIONS"));
+    }
+
+    #[tokio::test]
+    async fn test_url_for() {
+        let app = FlaskApp::new();
+        app.get(Rule::new("/users/{id:int}/posts").endpoint("user_posts"), |req, _params| {
+            let matched = req.extensions().get::<MatchedRoute>().cloned().unwrap_or_default();
+            matched.endpoint.unwrap_or_default()
+        })
+        .await;
+
+        let mut users = Blueprint::new("users");
+        users.get(Rule::new("/{name}").endpoint("show"), |_req, _params| "user");
+        let mut api = Blueprint::new("api");
+        api.register_blueprint(users, "/users");
+        app.register_blueprint(api, "/api").await;
+
+        assert_eq!(
+            app.url_for("user_posts", [("id", "7"), ("page", "2")]).await,
+            Ok("/users/7/posts?page=2".to_string())
+        );
+        assert_eq!(
+            app.url_for("api.users.show", [("name", "a b")]).await,
+            Ok("/api/users/a%20b".to_string())
+        );
+        // Only catch-alls may carry a '/'
+        assert!(matches!(
+            app.url_for("api.users.show", [("name", "a b/c")]).await,
+            Err(UrlBuildError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            app.url_for("user_posts", [("page", 2)]).await,
+            Err(UrlBuildError::MissingParam { .. })
+        ));
+        assert_eq!(
+            app.url_for("show", [("name", "x")]).await,
+            Err(UrlBuildError::UnknownEndpoint("show".to_string()))
+        );
+
+        assert_eq!(get(&app, "/users/7/posts").await.body, "user_posts");
+    }
+
+    #[tokio::test]
+    async fn test_canonical_redirect() {
+        let app = FlaskApp::with_config(FlaskConfig {
+            canonical_redirect: true,
+            ..FlaskConfig::default()
+        });
+        app.get("/hello/{name}", |_req, params| format!("Hello, {}!", params.get("name").unwrap_or_default()))
+            .await;
+
+        assert_eq!(get(&app, "/hello/J%C3%BCrgen").await.body, "Hello, Jürgen!");
+
+        let response = get(&app, "//hello//ferris?lang=en").await;
+        assert_eq!(response.status, StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.header(LOCATION.as_str()), Some("/hello/ferris?lang=en"));
+
+        // Unknown paths stay 404 rather than redirecting
+        assert_eq!(get(&app, "//nowhere").await.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_strict_slashes() {
+        let app = FlaskApp::new();
+        app.get("/docs/", |_req, _params| "docs").await;
+        app.get("/about", |_req, _params| "about").await;
+        app.get(Rule::new("/feed/").strict_slashes(false), |_req, _params| "feed").await;
+
+        let lenient = FlaskApp::with_config(FlaskConfig {
+            strict_slashes: false,
+            ..FlaskConfig::default()
+        });
+        lenient.get("/docs/", |_req, _params| "docs").await;
+        lenient.get("/about", |_req, _params| "about").await;
+
+        async fn redirect(app: &FlaskApp, uri: &str) -> (StatusCode, Option<String>) {
+            let response = get(app, uri).await;
+            let location = response.header(LOCATION.as_str()).map(str::to_string);
+            (response.status, location)
+        }
+
+        // A branch URL without its slash redirects; the reverse is a 404
+        assert_eq!(
+            redirect(&app, "/docs?page=2").await,
+            (StatusCode::PERMANENT_REDIRECT, Some("/docs/?page=2".into()))
+        );
+        assert_eq!(redirect(&app, "/docs/").await, (StatusCode::OK, None));
+        assert_eq!(redirect(&app, "/about/").await, (StatusCode::NOT_FOUND, None));
+        assert_eq!(redirect(&app, "/feed").await, (StatusCode::OK, None));
+
+        assert_eq!(redirect(&lenient, "/docs").await, (StatusCode::OK, None));
+        assert_eq!(redirect(&lenient, "/about/").await, (StatusCode::OK, None));
+        assert_eq!(redirect(&lenient, "/nowhere/").await, (StatusCode::NOT_FOUND, None));
+    }
 }
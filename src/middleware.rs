@@ -0,0 +1,276 @@
+// Middleware: code that wraps request handling and decides whether (and how)
+// to call the rest of the pipeline.
+//
+// Native middleware implement `Middleware` (async fns and `from_fn` closures
+// do automatically) and call `next.run(req).await` to continue:
+//
+//   async fn timing(req: Request<Body>, next: Next) -> Response<Body> {
+//       let (path, start) = (req.uri().path().to_string(), Instant::now());
+//       let response = next.run(req).await;
+//       println!("{} took {:?}", path, start.elapsed());
+//       response
+//   }
+//   let app = FlaskApp::new().middleware(timing).layer(ConcurrencyLimitLayer::new(64));
+//
+// Any `tower::Layer` whose service speaks `Request<Body>`/`Response<Body>`
+// plugs in with `layer`. Both apply globally on the app or to one route via
+// `Rule::middleware` / `Rule::layer`, and in either place the first one
+// registered sees the request first. App layers wrap app middleware, which
+// wrap routing, hooks and the handler. Errors from layered services (e.g. a
+// timeout) become responses: 408 for `Elapsed`, 500 otherwise.
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper::{Body, Request, Response, StatusCode};
+use tower::util::BoxCloneSyncService;
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+use crate::debug_page::ErrorReport;
+use crate::handler::{BoxFuture, RouteHandler};
+use crate::{IntoResponse, RouteParams};
+
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, req: Request<Body>, next: Next) -> impl Future<Output = Response<Body>> + Send;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Request<Body>, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send,
+{
+    fn handle(&self, req: Request<Body>, next: Next) -> impl Future<Output = Response<Body>> + Send {
+        self(req, next)
+    }
+}
+
+// Lets closure arguments be inferred: `from_fn(|req, next| async move { ... })`
+pub struct FromFn<F>(F);
+
+pub fn from_fn<F, Fut>(f: F) -> FromFn<F>
+where
+    F: Fn(Request<Body>, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send,
+{
+    FromFn(f)
+}
+
+impl<F, Fut> Middleware for FromFn<F>
+where
+    F: Fn(Request<Body>, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send,
+{
+    fn handle(&self, req: Request<Body>, next: Next) -> impl Future<Output = Response<Body>> + Send {
+        (self.0)(req, next)
+    }
+}
+
+pub(crate) type DynMiddleware = Arc<dyn Fn(Request<Body>, Next) -> BoxFuture<Response<Body>> + Send + Sync>;
+pub(crate) type Endpoint = Arc<dyn Fn(Request<Body>) -> BoxFuture<Response<Body>> + Send + Sync>;
+
+pub(crate) fn erase<M: Middleware>(middleware: M) -> DynMiddleware {
+    let middleware = Arc::new(middleware);
+    Arc::new(move |req, next| {
+        let middleware = Arc::clone(&middleware);
+        Box::pin(async move { middleware.handle(req, next).await })
+    })
+}
+
+// The rest of the pipeline after the current middleware
+pub struct Next {
+    chain: Arc<[DynMiddleware]>,
+    index: usize,
+    endpoint: Endpoint,
+}
+
+impl Next {
+    pub(crate) fn new(chain: Arc<[DynMiddleware]>, endpoint: Endpoint) -> Self {
+        Next {
+            chain,
+            index: 0,
+            endpoint,
+        }
+    }
+
+    pub async fn run(self, req: Request<Body>) -> Response<Body> {
+        match self.chain.get(self.index) {
+            Some(middleware) => {
+                let middleware = Arc::clone(middleware);
+                let next = Next {
+                    index: self.index + 1,
+                    ..self
+                };
+                middleware(req, next).await
+            }
+            None => (self.endpoint)(req).await,
+        }
+    }
+}
+
+// The type every tower layer is applied to
+pub type BoxedService = BoxCloneSyncService<Request<Body>, Response<Body>, Infallible>;
+
+// A layer with its service type erased; applied when the pipeline is built
+pub(crate) type DynLayer = Arc<dyn Fn(BoxedService) -> BoxedService + Send + Sync>;
+
+pub(crate) fn erase_layer<L>(layer: L) -> DynLayer
+where
+    L: Layer<BoxedService> + Send + Sync + 'static,
+    L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+    <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+{
+    Arc::new(move |inner| BoxCloneSyncService::new(HandleError(layer.layer(inner))))
+}
+
+// Turns a layered service's errors into responses so the stack stays
+// infallible. Readiness is checked by `oneshot` on a clone, per call.
+#[derive(Clone)]
+struct HandleError<S>(S);
+
+impl<S> Service<Request<Body>> for HandleError<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Result<Response<Body>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let service = self.0.clone();
+        Box::pin(async move {
+            Ok(match service.oneshot(req).await {
+                Ok(response) => response,
+                Err(error) => error_response(error.into()),
+            })
+        })
+    }
+}
+
+// Wraps `service` so the first layer in `layers` ends up outermost
+pub(crate) fn apply_layers(layers: &[DynLayer], service: BoxedService) -> BoxedService {
+    layers.iter().rev().fold(service, |service, layer| layer(service))
+}
+
+fn error_response(error: BoxError) -> Response<Body> {
+    if error.is::<tower::timeout::error::Elapsed>() {
+        return (StatusCode::REQUEST_TIMEOUT, "Request Timeout").into_response();
+    }
+    eprintln!("Service error: {}", error);
+    let mut response = (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+    response.extensions_mut().insert(ErrorReport::from_error(&*error));
+    response
+}
+
+// Per-route middleware and layers are applied to the route's handler once,
+// at registration
+pub(crate) type RouteWrap = Arc<dyn Fn(RouteHandler) -> RouteHandler + Send + Sync>;
+
+pub(crate) fn route_middleware<M: Middleware>(middleware: M) -> RouteWrap {
+    let chain: Arc<[DynMiddleware]> = Arc::from(vec![erase(middleware)]);
+    Arc::new(move |handler: RouteHandler| -> RouteHandler {
+        let chain = Arc::clone(&chain);
+        Arc::new(move |req, params| {
+            let handler = Arc::clone(&handler);
+            let endpoint: Endpoint = Arc::new(move |req| handler(req, params.clone()));
+            let next = Next::new(Arc::clone(&chain), endpoint);
+            Box::pin(next.run(req))
+        })
+    })
+}
+
+pub(crate) fn route_layer(layer: DynLayer) -> RouteWrap {
+    Arc::new(move |handler: RouteHandler| -> RouteHandler {
+        // The handler as a service; its parameters travel in the extensions
+        let service = tower::service_fn(move |req: Request<Body>| {
+            let params = req.extensions().get::<RouteParams>().cloned().unwrap_or_default();
+            let response = handler(req, params);
+            async move { Ok::<_, Infallible>(response.await) }
+        });
+        let service = layer(BoxCloneSyncService::new(service));
+        Arc::new(move |mut req, params| {
+            req.extensions_mut().insert(params);
+            let service = service.clone();
+            Box::pin(async move {
+                match service.oneshot(req).await {
+                    Ok(response) => response,
+                    Err(never) => match never {},
+                }
+            })
+        })
+    })
+}
+
+// Applies per-route wraps so the first one registered runs first
+pub(crate) fn wrap_route(wraps: &[RouteWrap], handler: RouteHandler) -> RouteHandler {
+    wraps.iter().rev().fold(handler, |handler, wrap| wrap(handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{get, send};
+    use crate::{FlaskApp, Rule};
+
+    #[tokio::test]
+    async fn test_middleware_and_layers() {
+        async fn require_key(req: Request<Body>, next: Next) -> Response<Body> {
+            if req.headers().contains_key("x-key") {
+                next.run(req).await
+            } else {
+                StatusCode::UNAUTHORIZED.into_response()
+            }
+        }
+
+        let tag = |name: &'static str| {
+            from_fn(move |req, next: Next| async move {
+                let mut response = next.run(req).await;
+                let trail = response
+                    .headers()
+                    .get("x-trail")
+                    .map(|v| format!("{},{}", v.to_str().unwrap(), name))
+                    .unwrap_or_else(|| name.to_string());
+                response.headers_mut().insert("x-trail", trail.parse().unwrap());
+                response
+            })
+        };
+
+        let app = FlaskApp::new()
+            .middleware(tag("outer"))
+            .middleware(tag("inner"))
+            .layer(tower::timeout::TimeoutLayer::new(std::time::Duration::from_secs(5)));
+        app.get("/open", |_req, _params| "open").await;
+        app.get(Rule::new("/private").middleware(require_key).middleware(tag("route")), |_req, _params| {
+            "private"
+        })
+        .await;
+        app.get(
+            Rule::new("/slow").layer(tower::timeout::TimeoutLayer::new(std::time::Duration::from_millis(20))),
+            |_req, _params| async {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                "too late"
+            },
+        )
+        .await;
+
+        // Responses pass back through the middleware innermost first
+        assert_eq!(get(&app, "/open").await.header("x-trail"), Some("inner,outer"));
+        let req = Request::builder().uri("/private").header("x-key", "1").body(Body::empty()).unwrap();
+        assert_eq!(send(&app, req).await.header("x-trail"), Some("route,inner,outer"));
+
+        // Route middleware can answer without calling the handler
+        let response = get(&app, "/private").await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.header("x-trail"), Some("inner,outer"));
+
+        assert_eq!(get(&app, "/slow").await.status, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(get(&app, "/missing").await.header("x-trail"), Some("inner,outer"));
+    }
+}
@@ -0,0 +1,384 @@
+// Streaming `multipart/form-data` parsing, Flask's `request.form` plus
+// `request.files`.
+//
+// Fields are read one at a time straight from the request body:
+//
+//   async fn upload(mut form: Multipart) -> Result<String, MultipartError> {
+//       while let Some(field) = form.next_field().await? {
+//           if field.file_name().is_some() {
+//               let file = field.into_upload().await?;
+//               file.save(format!("uploads/{}", file.file_name().unwrap())).await?;
+//           } else {
+//               let value = field.text().await?;
+//           }
+//       }
+//       ...
+//   }
+//
+// `MultipartLimits` (from `FlaskConfig::multipart`) caps each field and the
+// whole body with 413, and decides when an upload is spooled to a temporary
+// file instead of being kept in memory.
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use multer::{Constraints, SizeLimit};
+use tempfile::{NamedTempFile, TempPath};
+use tokio::io::AsyncWriteExt;
+
+use crate::body;
+use crate::extract::{has_content_type, FromRequest};
+use crate::IntoResponse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultipartLimits {
+    // Largest single field or file, in bytes
+    pub max_field_size: u64,
+    // Largest whole multipart body, in bytes
+    pub max_total_size: u64,
+    // Uploads bigger than this are written to a temporary file
+    pub max_in_memory_size: u64,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        MultipartLimits {
+            max_field_size: 16 * 1024 * 1024,
+            max_total_size: 64 * 1024 * 1024,
+            max_in_memory_size: 1024 * 1024,
+        }
+    }
+}
+
+pub struct Multipart {
+    inner: multer::Multipart<'static>,
+    limits: MultipartLimits,
+}
+
+impl Multipart {
+    pub async fn next_field(&mut self) -> Result<Option<Field>, MultipartError> {
+        let field = self.inner.next_field().await?;
+        Ok(field.map(|inner| Field {
+            inner,
+            limits: self.limits,
+        }))
+    }
+}
+
+impl FromRequest for Multipart {
+    type Rejection = MultipartError;
+
+    async fn from_request(req: Request<Body>) -> Result<Self, Self::Rejection> {
+        if !has_content_type(req.headers(), "multipart/form-data") {
+            return Err(MultipartError::UnsupportedMediaType);
+        }
+        let content_type = req.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+        let boundary = multer::parse_boundary(content_type)?;
+
+        let limits = req.extensions().get::<MultipartLimits>().copied().unwrap_or_default();
+        let constraints = Constraints::new().size_limit(
+            SizeLimit::new()
+                .per_field(limits.max_field_size)
+                .whole_stream(limits.max_total_size),
+        );
+        Ok(Multipart {
+            inner: multer::Multipart::with_constraints(req.into_body(), boundary, constraints),
+            limits,
+        })
+    }
+}
+
+// One form field or uploaded file. Read it (or drop it) before asking for
+// the next field.
+pub struct Field {
+    inner: multer::Field<'static>,
+    limits: MultipartLimits,
+}
+
+impl Field {
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name()
+    }
+
+    // Set for file inputs
+    pub fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.inner.content_type().map(|mime| mime.as_ref())
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    // The next piece of the field's data, for processing it incrementally
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        Ok(self.inner.chunk().await?)
+    }
+
+    pub async fn bytes(self) -> Result<Bytes, MultipartError> {
+        Ok(self.inner.bytes().await?)
+    }
+
+    pub async fn text(self) -> Result<String, MultipartError> {
+        Ok(self.inner.text().await?)
+    }
+
+    // Reads the whole field, spooling it to a temporary file once it grows
+    // past `max_in_memory_size`
+    pub async fn into_upload(mut self) -> Result<UploadedFile, MultipartError> {
+        let name = self.name().map(str::to_string);
+        let file_name = self.file_name().map(str::to_string);
+        let content_type = self.content_type().map(str::to_string);
+
+        let mut buffer = Vec::new();
+        let mut spooled: Option<(tokio::fs::File, TempPath)> = None;
+        let mut size = 0;
+        while let Some(chunk) = self.inner.chunk().await? {
+            size += chunk.len() as u64;
+            match &mut spooled {
+                Some((file, _)) => file.write_all(&chunk).await?,
+                None if size > self.limits.max_in_memory_size => {
+                    let (file, path) = NamedTempFile::new()?.into_parts();
+                    let mut file = tokio::fs::File::from_std(file);
+                    file.write_all(&buffer).await?;
+                    file.write_all(&chunk).await?;
+                    buffer = Vec::new();
+                    spooled = Some((file, path));
+                }
+                None => buffer.extend_from_slice(&chunk),
+            }
+        }
+
+        let data = match spooled {
+            Some((mut file, path)) => {
+                file.flush().await?;
+                UploadData::Disk(path)
+            }
+            None => UploadData::Memory(Bytes::from(buffer)),
+        };
+        Ok(UploadedFile {
+            name,
+            file_name,
+            content_type,
+            size,
+            data,
+        })
+    }
+}
+
+// A fully received upload, in memory or in a temporary file that is deleted
+// when this value is dropped
+#[derive(Debug)]
+pub struct UploadedFile {
+    name: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    size: u64,
+    data: UploadData,
+}
+
+#[derive(Debug)]
+enum UploadData {
+    Memory(Bytes),
+    Disk(TempPath),
+}
+
+impl UploadedFile {
+    // The form field name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // The client-supplied file name; never use it as a path unchecked
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    // The temporary file, when the upload was spooled to disk
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            UploadData::Memory(_) => None,
+            UploadData::Disk(path) => Some(path),
+        }
+    }
+
+    pub async fn bytes(&self) -> io::Result<Bytes> {
+        match &self.data {
+            UploadData::Memory(bytes) => Ok(bytes.clone()),
+            UploadData::Disk(path) => tokio::fs::read(path).await.map(Bytes::from),
+        }
+    }
+
+    pub async fn save(&self, destination: impl AsRef<Path>) -> io::Result<()> {
+        match &self.data {
+            UploadData::Memory(bytes) => tokio::fs::write(destination, bytes).await,
+            UploadData::Disk(path) => tokio::fs::copy(path, destination).await.map(|_| ()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MultipartError {
+    // The request is not `multipart/form-data`
+    UnsupportedMediaType,
+    FieldTooLarge { name: Option<String>, limit: u64 },
+    TooLarge { limit: u64 },
+    Malformed(String),
+    // Spooling an upload to disk failed
+    Io(io::Error),
+}
+
+impl MultipartError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            MultipartError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            MultipartError::FieldTooLarge { .. } | MultipartError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            MultipartError::Malformed(_) => StatusCode::BAD_REQUEST,
+            MultipartError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::UnsupportedMediaType => {
+                write!(f, "Expected request with `Content-Type: multipart/form-data`")
+            }
+            MultipartError::FieldTooLarge { name: Some(name), limit } => {
+                write!(f, "Field `{}` exceeds the limit of {} bytes", name, limit)
+            }
+            MultipartError::FieldTooLarge { name: None, limit } => {
+                write!(f, "A field exceeds the limit of {} bytes", limit)
+            }
+            MultipartError::TooLarge { limit } => {
+                write!(f, "Multipart body exceeds the limit of {} bytes", limit)
+            }
+            MultipartError::Malformed(msg) => write!(f, "Invalid multipart body: {}", msg),
+            MultipartError::Io(e) => write!(f, "Failed to store upload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+impl From<multer::Error> for MultipartError {
+    fn from(error: multer::Error) -> Self {
+        match error {
+            multer::Error::FieldSizeExceeded { limit, field_name } => MultipartError::FieldTooLarge {
+                name: field_name,
+                limit,
+            },
+            multer::Error::StreamSizeExceeded { limit } => MultipartError::TooLarge { limit },
+            // The route's `max_content_length` cut the body off
+            multer::Error::StreamReadFailed(e) => match body::limit_exceeded(e.as_ref()) {
+                Some(exceeded) => MultipartError::TooLarge { limit: exceeded.limit() },
+                None => MultipartError::Malformed(multer::Error::StreamReadFailed(e).to_string()),
+            },
+            other => MultipartError::Malformed(other.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for MultipartError {
+    fn from(error: io::Error) -> Self {
+        MultipartError::Io(error)
+    }
+}
+
+// Only the message is sent to the client; I/O details stay on the server
+impl IntoResponse for MultipartError {
+    fn into_response(self) -> Response<Body> {
+        if let MultipartError::Io(e) = &self {
+            eprintln!("Multipart upload failed: {}", e);
+            return (self.status(), "Failed to store upload").into_response();
+        }
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "X-BOUNDARY";
+
+    fn request(body: String, limits: MultipartLimits) -> Request<Body> {
+        let mut req = Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(Body::from(body))
+            .unwrap();
+        req.extensions_mut().insert(limits);
+        req
+    }
+
+    fn body(file: &str) -> String {
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHoliday\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"beach.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n{file}\r\n--{b}--\r\n",
+            b = BOUNDARY,
+            file = file
+        )
+    }
+
+    #[tokio::test]
+    async fn test_fields_and_spooled_upload() {
+        let limits = MultipartLimits {
+            max_in_memory_size: 8,
+            ..MultipartLimits::default()
+        };
+        let mut form = Multipart::from_request(request(body("sand and sea"), limits)).await.unwrap();
+
+        let title = form.next_field().await.unwrap().unwrap();
+        assert_eq!((title.name(), title.file_name()), (Some("title"), None));
+        assert_eq!(title.text().await.unwrap(), "Holiday");
+
+        let photo = form.next_field().await.unwrap().unwrap();
+        assert_eq!(photo.content_type(), Some("text/plain"));
+        let upload = photo.into_upload().await.unwrap();
+        assert_eq!(upload.file_name(), Some("beach.txt"));
+        assert_eq!(upload.len(), 12);
+        let path = upload.path().expect("spooled to disk").to_path_buf();
+        assert_eq!(&upload.bytes().await.unwrap()[..], b"sand and sea");
+
+        assert!(form.next_field().await.unwrap().is_none());
+        drop(upload);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_limits_and_content_type() {
+        let limits = MultipartLimits {
+            max_field_size: 4,
+            ..MultipartLimits::default()
+        };
+        let mut form = Multipart::from_request(request(body("x"), limits)).await.unwrap();
+        let err = form.next_field().await.unwrap().unwrap().text().await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(err.to_string(), "Field `title` exceeds the limit of 4 bytes");
+
+        let req = Request::builder().header(CONTENT_TYPE, "text/plain").body(Body::empty()).unwrap();
+        let err = Multipart::from_request(req).await.err().unwrap();
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -0,0 +1,257 @@
+// Panics in middleware, hooks and handlers become 500 responses.
+//
+// The request pipeline is polled inside `CatchUnwind`. To capture where a
+// panic happened, the first `CatchUnwind` installs a panic hook that records
+// the location of panics inside one and then calls the hook it replaced, so
+// the default message or an app's own reporter still sees every panic. A
+// backtrace is captured only when `RUST_BACKTRACE` asks for one, or always
+// for a `CatchUnwind` with `force_backtrace` (debug mode, for the debug
+// page). The 500 itself is logged once more with the request that caused it.
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+use hyper::{Body, Method, Response, StatusCode, Uri};
+
+use crate::debug_page::ErrorReport;
+use crate::errorhandlers;
+use crate::handler::BoxFuture;
+use crate::IntoResponse;
+
+// A caught panic
+#[derive(Debug)]
+pub(crate) struct Panic {
+    pub(crate) message: String,
+    pub(crate) location: Option<String>,
+    pub(crate) backtrace: Option<Backtrace>,
+}
+
+impl fmt::Display for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // How many `CatchUnwind`s are polling on this thread
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+    // Whether the innermost `CatchUnwind` polling wants a backtrace regardless
+    // of `RUST_BACKTRACE`
+    static FORCE_BACKTRACE: Cell<bool> = const { Cell::new(false) };
+    static CAUGHT: RefCell<Option<(Option<String>, Option<Backtrace>)>> = const { RefCell::new(None) };
+}
+
+fn install_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) > 0 {
+                let location = info.location().map(ToString::to_string);
+                let backtrace = match FORCE_BACKTRACE.with(Cell::get) {
+                    true => Some(Backtrace::force_capture()),
+                    false => Some(Backtrace::capture()).filter(|b| b.status() == BacktraceStatus::Captured),
+                };
+                CAUGHT.with(|caught| *caught.borrow_mut() = Some((location, backtrace)));
+            }
+            previous(info)
+        }));
+    });
+}
+
+// Polls a boxed future, turning a panic into `Err(Panic)` so cleanup code
+// can still run
+pub(crate) struct CatchUnwind<T> {
+    future: BoxFuture<T>,
+    force_backtrace: bool,
+}
+
+impl<T> CatchUnwind<T> {
+    pub(crate) fn new(future: BoxFuture<T>) -> Self {
+        CatchUnwind {
+            future,
+            force_backtrace: false,
+        }
+    }
+
+    // Captures a backtrace even when `RUST_BACKTRACE` is unset
+    pub(crate) fn force_backtrace(mut self, force: bool) -> Self {
+        self.force_backtrace = force;
+        self
+    }
+}
+
+impl<T> Future for CatchUnwind<T> {
+    type Output = Result<T, Panic>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        install_hook();
+        let force = FORCE_BACKTRACE.with(|force| force.replace(self.force_backtrace));
+        let future = self.future.as_mut();
+        // Drop what a panic caught elsewhere (e.g. by the handler itself) left
+        CAUGHT.with(|caught| caught.borrow_mut().take());
+        CATCHING.with(|depth| depth.set(depth.get() + 1));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| future.poll(cx)));
+        CATCHING.with(|depth| depth.set(depth.get() - 1));
+        FORCE_BACKTRACE.with(|cell| cell.set(force));
+        match result {
+            Ok(poll) => {
+                CAUGHT.with(|caught| caught.borrow_mut().take());
+                poll.map(Ok)
+            }
+            Err(payload) => {
+                let (location, backtrace) = CAUGHT.with(|caught| caught.borrow_mut().take()).unzip();
+                Poll::Ready(Err(Panic {
+                    message: panic_message(payload.as_ref()),
+                    location: location.flatten(),
+                    backtrace: backtrace.flatten(),
+                }))
+            }
+        }
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "handler panicked".to_string()
+    }
+}
+
+// Logs `panic` and answers a terse 500 carrying the details for the debug
+// page
+pub(crate) fn internal_error(method: &Method, uri: &Uri, panic: &Panic) -> Response<Body> {
+    eprintln!("Panic while handling {} {}: {}", method, uri, panic);
+    let mut response = (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+    response.extensions_mut().insert(ErrorReport {
+        message: panic.message.clone(),
+        chain: Vec::new(),
+        location: panic.location.clone(),
+        backtrace: panic.backtrace.as_ref().map(ToString::to_string),
+        error_type: None,
+    });
+    errorhandlers::mark(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::CONTENT_TYPE;
+    use hyper::{Body, Request};
+    use crate::testing::get;
+    use crate::{FlaskApp, FlaskConfig, Next};
+
+    #[tokio::test]
+    async fn test_catch_unwind_records_the_panic() {
+        let outcome = CatchUnwind::new(Box::pin(async { 1 })).await;
+        assert_eq!(outcome.unwrap(), 1);
+
+        let boom = || {
+            Box::pin(async {
+                tokio::task::yield_now().await;
+                panic!("boom {}", 7)
+            })
+        };
+        let panic = CatchUnwind::new(boom()).force_backtrace(true).await.unwrap_err();
+        assert_eq!(panic.message, "boom 7");
+        assert!(panic.location.unwrap().starts_with("src/panic.rs:"));
+        assert!(panic.backtrace.is_some());
+
+        // Otherwise only as `RUST_BACKTRACE` says
+        let panic = CatchUnwind::new(boom()).await.unwrap_err();
+        let wanted = Backtrace::capture().status() == BacktraceStatus::Captured;
+        assert_eq!(panic.backtrace.is_some(), wanted);
+    }
+
+    #[tokio::test]
+    async fn test_panics_caught_inside_leave_nothing_behind() {
+        let outcome = CatchUnwind::new(Box::pin(async {
+            std::panic::catch_unwind(|| panic!("handled")).is_err()
+        }))
+        .await;
+        assert!(outcome.unwrap());
+        assert!(CAUGHT.with(|caught| caught.borrow().is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_internal_error_is_terse_and_carries_a_report() {
+        let panic = Panic {
+            message: "index out of bounds".to_string(),
+            location: Some("src/main.rs:3:5".to_string()),
+            backtrace: None,
+        };
+        let uri: Uri = "/items/9".parse().unwrap();
+
+        let response = internal_error(&Method::GET, &uri, &panic);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let report = response.extensions().get::<ErrorReport>().unwrap();
+        assert_eq!(report.message, "index out of bounds");
+        assert_eq!(report.location.as_deref(), Some("src/main.rs:3:5"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"Internal Server Error");
+    }
+
+    #[tokio::test]
+    async fn test_panics_become_500() {
+        async fn app(debug: bool) -> FlaskApp {
+            let app = FlaskApp::with_config(FlaskConfig {
+                debug,
+                ..FlaskConfig::default()
+            })
+            .middleware(|req: Request<Body>, next: Next| async move {
+                if req.uri().path() == "/middleware" {
+                    panic!("middleware failed");
+                }
+                next.run(req).await
+            });
+            app.get("/items/{id}", |_req, params| -> String {
+                let items = ["apple"];
+                items[params.parse::<usize>("id").unwrap()].to_string()
+            })
+            .await;
+            app.errorhandler(StatusCode::INTERNAL_SERVER_ERROR, |_req, _response| async {
+                (StatusCode::INTERNAL_SERVER_ERROR, "sorry")
+            })
+            .await;
+            app
+        }
+
+        // Production: terse, through the 500 handler
+        let production = app(false).await;
+        let response = get(&production, "/items/3").await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.body, "sorry");
+        let response = get(&production, "/middleware").await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.body, "sorry");
+        assert_eq!(get(&production, "/items/0").await.body, "apple");
+
+        // Debug: the error page, even over the 500 handler
+        let debug = app(true).await;
+        let response = get(&debug, "/items/3").await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.header(CONTENT_TYPE.as_str()), Some("text/html; charset=utf-8"));
+        let html = response.body;
+        assert!(html.contains("index out of bounds"), "{}", html);
+        assert!(html.contains("<tr><th>Route</th><td>/items/{id}</td></tr>"), "{}", html);
+        assert!(html.contains("<tr><th>id</th><td>3</td></tr>"), "{}", html);
+        assert!(html.contains("<h3>Backtrace</h3>"), "{}", html);
+
+        let response = get(&debug, "/middleware").await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.body.contains("<h2>middleware failed</h2>"), "{}", response.body);
+        assert_eq!(get(&debug, "/items/0").await.body, "apple");
+    }
+}
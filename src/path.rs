@@ -0,0 +1,428 @@
+// Typed access to captured path parameters.
+//
+// `Path<T>` runs the route's captures through a small serde deserializer:
+// structs and maps are filled by parameter name, tuples and sequences by
+// position, and a bare scalar requires exactly one capture. Each value is
+// parsed from its string form with `FromStr`, so a failure can name the
+// offending parameter.
+use std::any::type_name;
+use std::fmt;
+use std::str::FromStr;
+
+use hyper::http::request::Parts;
+use hyper::{Body, Response, StatusCode};
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::extract::FromRequestParts;
+use crate::{IntoResponse, RouteParams};
+
+#[derive(Debug, Clone)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned + Send> FromRequestParts for Path<T> {
+    type Rejection = PathError;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        let params = parts.extensions.get::<RouteParams>().cloned().unwrap_or_default();
+        T::deserialize(PathDeserializer::new(&params)).map(Path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    // A captured segment could not be parsed as the requested type
+    Parse {
+        name: String,
+        value: String,
+        expected: &'static str,
+    },
+    // The target type asked for a parameter the route does not capture
+    Missing(String),
+    // A tuple asked for a different number of parameters than were captured
+    WrongCount { expected: usize, got: usize },
+    Message(String),
+}
+
+impl PathError {
+    // Bad input from the client is a 400; a type that does not fit the route
+    // pattern is a server-side bug.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            PathError::Parse { .. } | PathError::Message(_) => StatusCode::BAD_REQUEST,
+            PathError::Missing(_) | PathError::WrongCount { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Parse { name, value, expected } => write!(
+                f,
+                "Invalid URL parameter `{}`: cannot parse {:?} as {}",
+                name, value, expected
+            ),
+            PathError::Missing(name) => write!(f, "Missing URL parameter `{}`", name),
+            PathError::WrongCount { expected, got } => write!(
+                f,
+                "Expected {} URL parameter(s) but the route captured {}",
+                expected, got
+            ),
+            PathError::Message(msg) => write!(f, "Invalid URL parameters: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl de::Error for PathError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        PathError::Message(msg.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        PathError::Missing(field.to_string())
+    }
+}
+
+impl IntoResponse for PathError {
+    fn into_response(self) -> Response<Body> {
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+pub(crate) fn parse_param<T: FromStr>(name: &str, value: &str) -> Result<T, PathError> {
+    value.parse().map_err(|_| PathError::Parse {
+        name: name.to_string(),
+        value: value.to_string(),
+        expected: type_name::<T>(),
+    })
+}
+
+pub(crate) struct PathDeserializer<'de> {
+    params: Vec<(&'de str, &'de str)>,
+}
+
+impl<'de> PathDeserializer<'de> {
+    pub(crate) fn new(params: &'de RouteParams) -> Self {
+        PathDeserializer {
+            params: params.iter().collect(),
+        }
+    }
+
+    fn single(self) -> Result<ValueDeserializer<'de>, PathError> {
+        match self.params.as_slice() {
+            [(name, value)] => Ok(ValueDeserializer { name, value }),
+            _ => Err(PathError::WrongCount {
+                expected: 1,
+                got: self.params.len(),
+            }),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for PathDeserializer<'de> {
+    type Error = PathError;
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(ParamSeq {
+            params: self.params.into_iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.params.len() != len {
+            return Err(PathError::WrongCount {
+                expected: len,
+                got: self.params.len(),
+            });
+        }
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(ParamMap {
+            params: self.params.into_iter(),
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_identifier(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+}
+
+struct ParamSeq<'de> {
+    params: std::vec::IntoIter<(&'de str, &'de str)>,
+}
+
+impl<'de> SeqAccess<'de> for ParamSeq<'de> {
+    type Error = PathError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        match self.params.next() {
+            Some((name, value)) => seed.deserialize(ValueDeserializer { name, value }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.params.len())
+    }
+}
+
+struct ParamMap<'de> {
+    params: std::vec::IntoIter<(&'de str, &'de str)>,
+    value: Option<(&'de str, &'de str)>,
+}
+
+impl<'de> MapAccess<'de> for ParamMap<'de> {
+    type Error = PathError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        match self.params.next() {
+            Some((name, value)) => {
+                self.value = Some((name, value));
+                seed.deserialize(BorrowedStrDeserializer::new(name)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let (name, value) = self
+            .value
+            .take()
+            .ok_or_else(|| PathError::Message("value requested before key".to_string()))?;
+        seed.deserialize(ValueDeserializer { name, value })
+    }
+}
+
+// Deserializes one captured segment, parsing scalars from the raw string
+struct ValueDeserializer<'de> {
+    name: &'de str,
+    value: &'de str,
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident : $ty:ty),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                visitor.$visit(parse_param::<$ty>(self.name, self.value)?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = PathError;
+
+    parse_value! {
+        deserialize_bool => visit_bool: bool,
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_i128 => visit_i128: i128,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_u128 => visit_u128: u128,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+        deserialize_char => visit_char: char,
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_borrowed_str(self.value)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        BorrowedStrDeserializer::<PathError>::new(self.value)
+            .deserialize_enum(name, variants, visitor).map_err(|_| PathError::Parse {
+            name: self.name.to_string(),
+            value: self.value.to_string(),
+            expected: "one of the enum variants",
+        })
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    fn params(pairs: &[(&str, &str)]) -> RouteParams {
+        let mut params = RouteParams::new();
+        for (name, value) in pairs {
+            params.insert(name.to_string(), value.to_string());
+        }
+        params
+    }
+
+    fn deserialize<T: DeserializeOwned>(pairs: &[(&str, &str)]) -> Result<T, PathError> {
+        T::deserialize(PathDeserializer::new(&params(pairs)))
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct UserPost {
+        id: u64,
+        slug: String,
+        draft: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Format {
+        Json,
+        Html,
+    }
+
+    #[test]
+    fn test_scalars_tuples_and_structs() {
+        assert_eq!(deserialize::<u64>(&[("id", "42")]), Ok(42));
+        assert_eq!(
+            deserialize::<(u64, String)>(&[("id", "7"), ("name", "Jürgen")]),
+            Ok((7, "Jürgen".to_string()))
+        );
+        assert_eq!(
+            deserialize::<UserPost>(&[("slug", "hello"), ("id", "3")]),
+            Ok(UserPost {
+                id: 3,
+                slug: "hello".to_string(),
+                draft: None,
+            })
+        );
+        assert_eq!(deserialize::<Format>(&[("format", "json")]), Ok(Format::Json));
+        assert_eq!(deserialize::<Format>(&[("format", "html")]), Ok(Format::Html));
+    }
+
+    #[test]
+    fn test_errors_name_the_parameter() {
+        let err = deserialize::<(u64, String)>(&[("id", "abc"), ("name", "x")]).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.to_string(), "Invalid URL parameter `id`: cannot parse \"abc\" as u64");
+
+        let err = deserialize::<UserPost>(&[("id", "1")]).unwrap_err();
+        assert_eq!(err, PathError::Missing("slug".to_string()));
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let err = deserialize::<(u64, u64)>(&[("id", "1")]).unwrap_err();
+        assert_eq!(err, PathError::WrongCount { expected: 2, got: 1 });
+
+        assert!(matches!(
+            deserialize::<Format>(&[("format", "xml")]),
+            Err(PathError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_route_params_parse() {
+        let params = params(&[("id", "42"), ("ratio", "0.5")]);
+        assert_eq!(params.parse::<u64>("id"), Ok(42));
+        assert_eq!(params.parse::<f64>("ratio"), Ok(0.5));
+        assert_eq!(params.parse::<u64>("missing"), Err(PathError::Missing("missing".to_string())));
+        assert!(matches!(params.parse::<u8>("ratio"), Err(PathError::Parse { expected: "u8", .. })));
+    }
+}
@@ -0,0 +1,153 @@
+// Query string access, Flask's `request.args`.
+//
+// `Args` keeps every `key=value` pair in order, so repeated keys such as
+// `?tag=a&tag=b` are preserved. Keys and values are percent-decoded and `+`
+// is read as a space; invalid UTF-8 is replaced rather than rejected.
+use std::any::type_name;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use hyper::http::request::Parts;
+use hyper::{Body, Response, StatusCode, Uri};
+use serde::de::DeserializeOwned;
+
+use crate::extract::FromRequestParts;
+use crate::IntoResponse;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+    pairs: Vec<(String, String)>,
+}
+
+impl Args {
+    pub fn parse(query: &str) -> Self {
+        Args {
+            pairs: form_urlencoded::parse(query.as_bytes()).into_owned().collect(),
+        }
+    }
+
+    pub fn from_uri(uri: &Uri) -> Self {
+        Self::parse(uri.query().unwrap_or(""))
+    }
+
+    // The first value for `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs.iter().filter(|(k, _)| k == key).map(|(_, v)| v.as_str()).collect()
+    }
+
+    // `Ok(None)` when the key is absent; an error naming the key when the
+    // value does not parse, e.g. `args.get_as::<u32>("page")?`
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Result<Option<T>, QueryError> {
+        match self.get(key) {
+            Some(value) => value.parse().map(Some).map_err(|_| QueryError {
+                name: Some(key.to_string()),
+                message: format!("cannot parse {:?} as {}", value, type_name::<T>()),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl FromRequestParts for Args {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(Args::from_uri(&parts.uri))
+    }
+}
+
+// A malformed query string; answers 400 and names the parameter when known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    name: Option<String>,
+    message: String,
+}
+
+impl QueryError {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "Invalid query parameter `{}`: {}", name, self.message),
+            None => write!(f, "Invalid query string: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl IntoResponse for QueryError {
+    fn into_response(self) -> Response<Body> {
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+// Deserializes `T` from a query string, tracking which field failed
+pub(crate) fn deserialize<T: DeserializeOwned>(query: &str) -> Result<T, QueryError> {
+    let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        QueryError {
+            name: (path != ".").then_some(path),
+            message: e.into_inner().to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_decoding_and_multiple_values() {
+        let args = Args::parse("q=rust+web%20framework&tag=a&tag=b%2Fc&caf%C3%A9=1&empty=");
+        assert_eq!(args.get("q"), Some("rust web framework"));
+        assert_eq!(args.get("tag"), Some("a"));
+        assert_eq!(args.get_all("tag"), vec!["a", "b/c"]);
+        assert_eq!(args.get("café"), Some("1"));
+        assert_eq!(args.get("empty"), Some(""));
+        assert_eq!(args.get("missing"), None);
+        assert!(args.get_all("missing").is_empty());
+        assert_eq!(args.len(), 5);
+    }
+
+    #[test]
+    fn test_get_as_names_the_field() {
+        let args = Args::parse("page=2&limit=ten");
+        assert_eq!(args.get_as::<u32>("page"), Ok(Some(2)));
+        assert_eq!(args.get_as::<u32>("offset"), Ok(None));
+
+        let err = args.get_as::<u32>("limit").unwrap_err();
+        assert_eq!(err.name(), Some("limit"));
+        assert_eq!(err.to_string(), "Invalid query parameter `limit`: cannot parse \"ten\" as u32");
+    }
+}
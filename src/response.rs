@@ -0,0 +1,203 @@
+// Conversions from handler return values into hyper responses, so handlers
+// can return `(body, status, headers)`-style values the way Flask views do.
+use std::convert::Infallible;
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use serde::Serialize;
+
+use crate::errorhandlers;
+use crate::{text_response, Json};
+
+pub trait IntoResponse {
+    fn into_response(self) -> Response<Body>;
+}
+
+impl IntoResponse for Response<Body> {
+    fn into_response(self) -> Response<Body> {
+        self
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response<Body> {
+        text_response(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        response
+    }
+}
+
+impl IntoResponse for Infallible {
+    fn into_response(self) -> Response<Body> {
+        match self {}
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Response<Body> {
+        Response::new(Body::empty())
+    }
+}
+
+// A bare status code responds with its canonical reason as the body
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response<Body> {
+        let mut response = self.canonical_reason().unwrap_or("").into_response();
+        *response.status_mut() = self;
+        response
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response<Body> {
+        match serde_json::to_vec(&self.0) {
+            Ok(json) => {
+                let mut response = Response::new(Body::from(json));
+                response.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("application/json; charset=utf-8"),
+                );
+                response
+            }
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to serialize JSON: {}", e),
+            )
+                .into_response(),
+        }
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for (StatusCode, R) {
+    fn into_response(self) -> Response<Body> {
+        let (status, body) = self;
+        let mut response = body.into_response();
+        *response.status_mut() = status;
+        response
+    }
+}
+
+// Headers given here replace any the body set (e.g. a custom Content-Type).
+// `extend` clears each name once and appends all of its values, so repeated
+// headers like Set-Cookie are kept.
+impl<R: IntoResponse> IntoResponse for (StatusCode, HeaderMap, R) {
+    fn into_response(self) -> Response<Body> {
+        let (status, headers, body) = self;
+        let mut response = (status, body).into_response();
+        response.headers_mut().extend(headers);
+        response
+    }
+}
+
+// `Err` goes through the app's error handlers, see `FlaskApp::errorhandler_for`
+impl<T: IntoResponse, E: IntoResponse + 'static> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response<Body> {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => errorhandlers::raise(err),
+        }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Option<T> {
+    fn into_response(self) -> Response<Body> {
+        match self {
+            Some(value) => value.into_response(),
+            None => errorhandlers::mark(StatusCode::NOT_FOUND.into_response()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::get;
+    use crate::FlaskApp;
+
+    async fn body_string(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_strings_are_utf8_text() {
+        let response = "héllo".into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Type"], "text/plain; charset=utf-8");
+        assert_eq!(body_string(response).await, "héllo");
+
+        let response = String::from("world").into_response();
+        assert_eq!(response.headers()["Content-Type"], "text/plain; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_json_with_status_and_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Request-Id", HeaderValue::from_static("abc"));
+        let response = (
+            StatusCode::CREATED,
+            headers,
+            Json(serde_json::json!({"id": 7})),
+        )
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["X-Request-Id"], "abc");
+        assert_eq!(response.headers()["Content-Type"], "application/json; charset=utf-8");
+        assert_eq!(body_string(response).await, r#"{"id":7}"#);
+    }
+
+    #[test]
+    fn test_repeated_headers_are_kept() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", HeaderValue::from_static("a=1"));
+        headers.append("Set-Cookie", HeaderValue::from_static("b=2"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/csv"));
+        let response = (StatusCode::OK, headers, "x,y").into_response();
+
+        let cookies: Vec<_> = response.headers().get_all("Set-Cookie").iter().collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+        let content_types: Vec<_> = response.headers().get_all(CONTENT_TYPE).iter().collect();
+        assert_eq!(content_types, ["text/csv"]);
+    }
+
+    #[test]
+    fn test_result_and_option() {
+        let ok: Result<&'static str, (StatusCode, &'static str)> = Ok("fine");
+        assert_eq!(ok.into_response().status(), StatusCode::OK);
+
+        let err: Result<&'static str, (StatusCode, &'static str)> = Err((StatusCode::CONFLICT, "taken"));
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+
+        let missing: Option<String> = None;
+        assert_eq!(missing.into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_handlers_return_into_response_values() {
+        let app = FlaskApp::new();
+        app.get("/users/{id}", |_req, params| {
+            let id = params.get("id").unwrap_or_default().to_string();
+            if id == "0" {
+                None
+            } else {
+                Some((StatusCode::ACCEPTED, format!("User {}", id)))
+            }
+        })
+        .await;
+
+        let response = get(&app, "/users/7").await;
+        assert_eq!(response.status, StatusCode::ACCEPTED);
+        assert_eq!(response.body, "User 7");
+        assert_eq!(get(&app, "/users/0").await.status, StatusCode::NOT_FOUND);
+    }
+}
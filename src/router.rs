@@ -0,0 +1,817 @@
+// Segment trie used by FlaskApp to dispatch requests.
+//
+// Patterns are split on '/' and inserted one segment per level. Each node
+// keeps its static children in a map, its `{param}` children (converter
+// constrained ones ahead of plain strings, otherwise in registration order)
+// and at most one `{*wildcard}` / `{name:path}` catch-all. Lookup walks
+// the tree depth-first with a fixed precedence (static > param > wildcard)
+// and backtracks when a branch dead-ends, so the winner never depends on
+// hash map iteration order and dispatch cost grows with path depth rather
+// than with the number of registered routes.
+//
+// Request paths are matched in normalized form: runs of '/' collapse into
+// one and each segment is percent-decoded before it is compared or captured,
+// so `/hello/J%C3%BCrgen` gives `name = "Jürgen"`. A `{param}` never captures
+// an encoded slash (`%2F`); catch-alls may.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use hyper::Method;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::converters::{
+    AnyConverter, Converter, ConverterSpec, FloatConverter, IntConverter, RegexConverter, StringConverter,
+    UuidConverter,
+};
+use crate::RouteParams;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    InvalidPattern(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidPattern(msg) => write!(f, "Invalid route pattern: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlBuildError {
+    UnknownEndpoint(String),
+    MissingParam { endpoint: String, param: String },
+    // The value would not be accepted by the parameter's converter, or has a
+    // '/' outside a catch-all
+    InvalidValue { param: String, value: String },
+}
+
+impl fmt::Display for UrlBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlBuildError::UnknownEndpoint(endpoint) => write!(f, "Could not build url for endpoint {:?}", endpoint),
+            UrlBuildError::MissingParam { endpoint, param } => write!(
+                f,
+                "Could not build url for endpoint {:?}: missing value for parameter `{}`",
+                endpoint, param
+            ),
+            UrlBuildError::InvalidValue { param, value } => {
+                write!(f, "Value {:?} is not valid for URL parameter `{}`", value, param)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UrlBuildError {}
+
+// Characters escaped inside a single path segment; '/' is kept for catch-alls
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Debug)]
+pub struct Match<'a, T> {
+    pub value: &'a T,
+    pub params: RouteParams,
+    pub pattern: &'a str,
+}
+
+pub enum Lookup<'a, T> {
+    Found(Match<'a, T>),
+    // The path matched, but only for these methods
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+pub struct Router<T> {
+    root: Node<T>,
+    len: usize,
+    converters: HashMap<String, Arc<dyn Converter>>,
+    // Endpoint name -> patterns, in registration order
+    endpoints: HashMap<String, Vec<String>>,
+}
+
+struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    params: Vec<ParamChild<T>>,
+    wildcard: Option<(String, Box<Node<T>>)>,
+    endpoints: Endpoints<T>,
+}
+
+// Handlers attached to a node. A `None` method accepts any request method and
+// is only consulted after the method-specific handlers.
+struct Endpoints<T> {
+    pattern: String,
+    by_method: Vec<(Method, T)>,
+    any: Option<T>,
+}
+
+struct ParamChild<T> {
+    name: String,
+    // Canonical converter spec, e.g. "int" or "any(a,b)"
+    spec: String,
+    converter: Arc<dyn Converter>,
+    node: Node<T>,
+}
+
+enum Segment<'p> {
+    Static(&'p str),
+    Param(&'p str, ConverterSpec<'p>),
+    Wildcard(&'p str),
+}
+
+impl<T> Router<T> {
+    pub fn new() -> Self {
+        let mut converters: HashMap<String, Arc<dyn Converter>> = HashMap::new();
+        converters.insert("int".to_string(), Arc::new(IntConverter));
+        converters.insert("float".to_string(), Arc::new(FloatConverter));
+        converters.insert("uuid".to_string(), Arc::new(UuidConverter));
+
+        Router {
+            root: Node::new(),
+            len: 0,
+            converters,
+            endpoints: HashMap::new(),
+        }
+    }
+
+    // Makes `{name:<converter name>}` available to patterns inserted afterwards
+    pub fn register_converter<C: Converter>(&mut self, name: &str, converter: C) {
+        self.converters.insert(name.to_string(), Arc::new(converter));
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, method: Option<Method>, pattern: &str, value: T) -> Result<Option<T>, RouteError> {
+        let segments = parse_pattern(pattern)?;
+
+        let mut node = &mut self.root;
+        for segment in segments {
+            node = match segment {
+                Segment::Static(s) => node.statics.entry(s.to_string()).or_insert_with(Node::new),
+                Segment::Param(name, spec) => {
+                    let key = spec.to_string();
+                    let index = match node.params.iter().position(|p| p.name == name && p.spec == key) {
+                        Some(index) => index,
+                        None => {
+                            let child = ParamChild {
+                                name: name.to_string(),
+                                converter: resolve_converter(&self.converters, pattern, &spec)?,
+                                spec: key,
+                                node: Node::new(),
+                            };
+                            // Plain string params stay behind every constrained param
+                            let index = if spec == ConverterSpec::Default {
+                                node.params.len()
+                            } else {
+                                node.params
+                                    .iter()
+                                    .position(|p| p.spec == "string")
+                                    .unwrap_or(node.params.len())
+                            };
+                            node.params.insert(index, child);
+                            index
+                        }
+                    };
+                    &mut node.params[index].node
+                }
+                Segment::Wildcard(name) => {
+                    match &node.wildcard {
+                        Some((existing, _)) if existing != name => {
+                            return Err(RouteError::InvalidPattern(format!(
+                                "{} (wildcard {{*{}}} already declared as {{*{}}})",
+                                pattern, name, existing
+                            )));
+                        }
+                        Some(_) => {}
+                        None => node.wildcard = Some((name.to_string(), Box::new(Node::new()))),
+                    }
+                    &mut node.wildcard.as_mut().unwrap().1
+                }
+            };
+        }
+
+        let endpoints = &mut node.endpoints;
+        if endpoints.pattern.is_empty() {
+            endpoints.pattern = pattern.to_string();
+        }
+
+        // Re-registering a method/pattern pair replaces the previous handler,
+        // matching HashMap::insert semantics.
+        let previous = match method {
+            Some(m) => match endpoints.by_method.iter_mut().find(|(existing, _)| *existing == m) {
+                Some((_, slot)) => Some(std::mem::replace(slot, value)),
+                None => {
+                    endpoints.by_method.push((m, value));
+                    None
+                }
+            },
+            None => endpoints.any.replace(value),
+        };
+        if previous.is_none() {
+            self.len += 1;
+        }
+        Ok(previous)
+    }
+
+    pub fn at<'a>(&'a self, method: &Method, path: &str) -> Option<Match<'a, T>> {
+        let segments = decode_path(path);
+        let mut captures = Vec::new();
+        let (node, value) = self.root.find(method, &segments, &mut captures)?;
+
+        let mut params = RouteParams::new();
+        for (name, value) in captures {
+            params.insert(name.to_string(), value);
+        }
+
+        Some(Match {
+            value,
+            params,
+            pattern: &node.endpoints.pattern,
+        })
+    }
+
+    // Names `pattern` so `url_for` can build it. An endpoint may name several
+    // patterns; the first one whose parameters are all supplied is used.
+    pub fn name(&mut self, endpoint: &str, pattern: &str) -> Result<(), RouteError> {
+        parse_pattern(pattern)?;
+        let patterns = self.endpoints.entry(endpoint.to_string()).or_default();
+        if !patterns.iter().any(|p| p == pattern) {
+            patterns.push(pattern.to_string());
+        }
+        Ok(())
+    }
+
+    // Reverse routing: fills the endpoint's `{param}` placeholders from
+    // `values`, percent-encoding them, and appends unused values as a query
+    // string.
+    pub fn url_for(&self, endpoint: &str, values: &[(String, String)]) -> Result<String, UrlBuildError> {
+        let patterns = self
+            .endpoints
+            .get(endpoint)
+            .ok_or_else(|| UrlBuildError::UnknownEndpoint(endpoint.to_string()))?;
+
+        let mut first_error = None;
+        for pattern in patterns {
+            match self.build(endpoint, pattern, values) {
+                Ok(url) => return Ok(url),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| UrlBuildError::UnknownEndpoint(endpoint.to_string())))
+    }
+
+    fn build(&self, endpoint: &str, pattern: &str, values: &[(String, String)]) -> Result<String, UrlBuildError> {
+        let segments = parse_pattern(pattern).map_err(|_| UrlBuildError::UnknownEndpoint(endpoint.to_string()))?;
+        let value_of = |param: &str| {
+            values
+                .iter()
+                .find(|(name, _)| name == param)
+                .map(|(_, value)| value.as_str())
+                .ok_or_else(|| UrlBuildError::MissingParam {
+                    endpoint: endpoint.to_string(),
+                    param: param.to_string(),
+                })
+        };
+
+        let mut used = Vec::new();
+        let mut url = String::new();
+        for segment in segments {
+            url.push('/');
+            match segment {
+                Segment::Static(s) => url.push_str(s),
+                Segment::Param(name, spec) => {
+                    let value = value_of(name)?;
+                    let converter = resolve_converter(&self.converters, pattern, &spec)
+                        .map_err(|_| UrlBuildError::UnknownEndpoint(endpoint.to_string()))?;
+                    // The router refuses '/' (even encoded) in a `{param}`, so
+                    // such a URL could never be routed back here
+                    if value.contains('/') || !converter.accepts(value) {
+                        return Err(UrlBuildError::InvalidValue {
+                            param: name.to_string(),
+                            value: value.to_string(),
+                        });
+                    }
+                    url.extend(utf8_percent_encode(value, SEGMENT));
+                    used.push(name);
+                }
+                Segment::Wildcard(name) => {
+                    let value = value_of(name)?;
+                    let encoded: Vec<String> = value
+                        .split('/')
+                        .map(|part| utf8_percent_encode(part, SEGMENT).to_string())
+                        .collect();
+                    url.push_str(&encoded.join("/"));
+                    used.push(name);
+                }
+            }
+        }
+
+        let leftover: Vec<(&str, &str)> = values
+            .iter()
+            .filter(|(name, _)| !used.contains(&name.as_str()))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if !leftover.is_empty() {
+            url.push('?');
+            url.push_str(&serde_urlencoded::to_string(leftover).unwrap_or_default());
+        }
+        Ok(url)
+    }
+
+    // Like `at`, but tells a path with no handler for `method` apart from an
+    // unknown path
+    pub fn lookup<'a>(&'a self, method: &Method, path: &str) -> Lookup<'a, T> {
+        if let Some(found) = self.at(method, path) {
+            return Lookup::Found(found);
+        }
+
+        let allowed = self.allowed_methods(path);
+        if allowed.is_empty() {
+            Lookup::NotFound
+        } else {
+            Lookup::MethodNotAllowed(allowed)
+        }
+    }
+
+    // Every method registered for any pattern matching `path`, sorted, with
+    // HEAD implied by GET
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods = Vec::new();
+        self.root.collect_methods(&decode_path(path), &mut methods);
+        if methods.contains(&Method::GET) {
+            methods.push(Method::HEAD);
+        }
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        methods.dedup();
+        methods
+    }
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Node<T> {
+    fn new() -> Self {
+        Node {
+            statics: HashMap::new(),
+            params: Vec::new(),
+            wildcard: None,
+            endpoints: Endpoints {
+                pattern: String::new(),
+                by_method: Vec::new(),
+                any: None,
+            },
+        }
+    }
+
+    fn find<'a>(
+        &'a self,
+        method: &Method,
+        segments: &[Cow<'_, str>],
+        captures: &mut Vec<(&'a str, String)>,
+    ) -> Option<(&'a Node<T>, &'a T)> {
+        let Some((first, rest)) = segments.split_first() else {
+            return self.endpoints.get(method).map(|value| (self, value));
+        };
+
+        if let Some(child) = self.statics.get(first.as_ref()) {
+            if let Some(found) = child.find(method, rest, captures) {
+                return Some(found);
+            }
+        }
+
+        for child in &self.params {
+            if !accepts(child, first) {
+                continue;
+            }
+            captures.push((&child.name, first.to_string()));
+            if let Some(found) = child.node.find(method, rest, captures) {
+                return Some(found);
+            }
+            captures.pop();
+        }
+
+        if let Some((name, child)) = &self.wildcard {
+            if !first.is_empty() {
+                if let Some(value) = child.endpoints.get(method) {
+                    captures.push((name, segments.join("/")));
+                    return Some((child, value));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn collect_methods(&self, segments: &[Cow<'_, str>], methods: &mut Vec<Method>) {
+        let Some((first, rest)) = segments.split_first() else {
+            methods.extend(self.endpoints.by_method.iter().map(|(m, _)| m.clone()));
+            return;
+        };
+
+        if let Some(child) = self.statics.get(first.as_ref()) {
+            child.collect_methods(rest, methods);
+        }
+        for child in &self.params {
+            if accepts(child, first) {
+                child.node.collect_methods(rest, methods);
+            }
+        }
+        if let Some((_, child)) = &self.wildcard {
+            if !first.is_empty() {
+                methods.extend(child.endpoints.by_method.iter().map(|(m, _)| m.clone()));
+            }
+        }
+    }
+}
+
+impl<T> Endpoints<T> {
+    // HEAD falls back to the GET handler when it has none of its own
+    fn get(&self, method: &Method) -> Option<&T> {
+        self.exact(method)
+            .or_else(|| if *method == Method::HEAD { self.exact(&Method::GET) } else { None })
+            .or(self.any.as_ref())
+    }
+
+    fn exact(&self, method: &Method) -> Option<&T> {
+        self.by_method.iter().find(|(m, _)| m == method).map(|(_, value)| value)
+    }
+}
+
+// A decoded '/' would let a single-segment capture span segments
+fn accepts<T>(child: &ParamChild<T>, segment: &str) -> bool {
+    !segment.contains('/') && child.converter.accepts(segment)
+}
+
+// "/" -> [""], "/users/1" -> ["users", "1"], "/users/" -> ["users", ""]
+fn split_path(path: &str) -> Vec<&str> {
+    path.strip_prefix('/').unwrap_or(path).split('/').collect()
+}
+
+// Like `split_path`, but a '/' inside `{...}` belongs to the parameter, so
+// `{v:regex([^/]+)}` stays one segment; a backslash escapes the next char
+fn split_pattern(pattern: &str) -> Vec<&str> {
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    let mut parts = Vec::new();
+    let (mut start, mut depth, mut escaped) = (0, 0usize, false);
+    for (i, c) in pattern.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            '/' if depth == 0 => {
+                parts.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&pattern[start..]);
+    parts
+}
+
+// The segments a request path is matched with: empty segments from repeated
+// slashes dropped (a trailing one is kept), then percent-decoded; invalid
+// UTF-8 is replaced, as Werkzeug does
+fn decode_path(path: &str) -> Vec<Cow<'_, str>> {
+    let segments = split_path(path);
+    let last = segments.len() - 1;
+    segments
+        .into_iter()
+        .enumerate()
+        .filter(|(i, segment)| !segment.is_empty() || *i == last)
+        .map(|(_, segment)| percent_decode_str(segment).decode_utf8_lossy())
+        .collect()
+}
+
+// The canonical form of a request path: runs of '/' collapsed into one,
+// e.g. "//users///7/" -> "/users/7/"
+pub fn normalize_path(path: &str) -> Cow<'_, str> {
+    if !path.contains("//") {
+        return Cow::Borrowed(path);
+    }
+    let mut normalized = String::with_capacity(path.len());
+    for c in path.chars() {
+        if !(c == '/' && normalized.ends_with('/')) {
+            normalized.push(c);
+        }
+    }
+    Cow::Owned(normalized)
+}
+
+// `{name}`, `{name:converter}` and `{*name}` (shorthand for `{name:path}`)
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment<'_>>, RouteError> {
+    if !pattern.starts_with('/') {
+        return Err(RouteError::InvalidPattern(format!("{} (must start with '/')", pattern)));
+    }
+
+    let parts = split_pattern(pattern);
+    let mut segments = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        let segment = if part.starts_with('{') && part.ends_with('}') {
+            let inner = &part[1..part.len() - 1];
+            let (name, spec) = match inner.strip_prefix('*') {
+                Some(name) => (name, ConverterSpec::Path),
+                None => match inner.split_once(':') {
+                    Some((name, spec)) => (name, ConverterSpec::parse(spec)),
+                    None => (inner, ConverterSpec::Default),
+                },
+            };
+            let name = validate_name(pattern, name.trim())?;
+            if spec != ConverterSpec::Path {
+                Segment::Param(name, spec)
+            } else if i != parts.len() - 1 {
+                return Err(RouteError::InvalidPattern(format!(
+                    "{} (catch-all {{{}}} must be the last segment)",
+                    pattern, inner
+                )));
+            } else {
+                Segment::Wildcard(name)
+            }
+        } else if part.contains('{') || part.contains('}') {
+            return Err(RouteError::InvalidPattern(format!(
+                "{} (parameters must span a whole segment)",
+                pattern
+            )));
+        } else {
+            Segment::Static(part)
+        };
+        segments.push(segment);
+    }
+    Ok(segments)
+}
+
+fn resolve_converter(
+    converters: &HashMap<String, Arc<dyn Converter>>,
+    pattern: &str,
+    spec: &ConverterSpec<'_>,
+) -> Result<Arc<dyn Converter>, RouteError> {
+    match spec {
+        ConverterSpec::Default => Ok(Arc::new(StringConverter)),
+        ConverterSpec::Any(values) => Ok(Arc::new(AnyConverter::new(values.iter().copied()))),
+        ConverterSpec::Regex(regex) => RegexConverter::new(regex)
+            .map(|c| Arc::new(c) as Arc<dyn Converter>)
+            .map_err(|e| RouteError::InvalidPattern(format!("{} ({})", pattern, e))),
+        ConverterSpec::Named(name) => converters.get(*name).cloned().ok_or_else(|| {
+            RouteError::InvalidPattern(format!("{} (unknown converter `{}`)", pattern, name))
+        }),
+        ConverterSpec::Path => unreachable!("path converters are parsed as wildcards"),
+    }
+}
+
+fn validate_name<'p>(pattern: &str, name: &'p str) -> Result<&'p str, RouteError> {
+    if name.is_empty() {
+        return Err(RouteError::InvalidPattern(format!("{} (empty parameter name)", pattern)));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(patterns: &[(&str, &'static str)]) -> Router<&'static str> {
+        let mut router = Router::new();
+        for (pattern, name) in patterns {
+            router.insert(Some(Method::GET), pattern, *name).unwrap();
+        }
+        router
+    }
+
+    #[test]
+    fn test_static_beats_param_beats_wildcard() {
+        let router = router(&[
+            ("/files/{*path}", "wildcard"),
+            ("/files/{name}", "param"),
+            ("/files/readme", "static"),
+        ]);
+
+        assert_eq!(*router.at(&Method::GET, "/files/readme").unwrap().value, "static");
+        assert_eq!(*router.at(&Method::GET, "/files/other").unwrap().value, "param");
+
+        let m = router.at(&Method::GET, "/files/a/b/c").unwrap();
+        assert_eq!(*m.value, "wildcard");
+        assert_eq!(m.params.get("path"), Some("a/b/c"));
+        assert_eq!(m.pattern, "/files/{*path}");
+    }
+
+    #[test]
+    fn test_backtracks_out_of_static_branch() {
+        let router = router(&[
+            ("/users/new/edit", "new-edit"),
+            ("/users/{id}/posts", "posts"),
+        ]);
+
+        let m = router.at(&Method::GET, "/users/new/posts").unwrap();
+        assert_eq!(*m.value, "posts");
+        assert_eq!(m.params.get("id"), Some("new"));
+    }
+
+    #[test]
+    fn test_method_specific_before_any() {
+        let mut router = Router::new();
+        router.insert(None, "/items", "any").unwrap();
+        router.insert(Some(Method::POST), "/items", "post").unwrap();
+
+        assert_eq!(*router.at(&Method::POST, "/items").unwrap().value, "post");
+        assert_eq!(*router.at(&Method::GET, "/items").unwrap().value, "any");
+        assert!(router.at(&Method::GET, "/items/1").is_none());
+    }
+
+    #[test]
+    fn test_root_and_trailing_slash_are_distinct() {
+        let router = router(&[("/", "root"), ("/users", "users")]);
+
+        assert_eq!(*router.at(&Method::GET, "/").unwrap().value, "root");
+        assert_eq!(*router.at(&Method::GET, "/users").unwrap().value, "users");
+        assert!(router.at(&Method::GET, "/users/").is_none());
+    }
+
+    #[test]
+    fn test_paths_are_decoded_and_normalized() {
+        let router = router(&[
+            ("/hello/{name}", "hello"),
+            ("/café", "cafe"),
+            ("/files/{*path}", "files"),
+            ("/users/{id:int}/", "user"),
+        ]);
+
+        let m = router.at(&Method::GET, "/hello/J%C3%BCrgen").unwrap();
+        assert_eq!(m.params.get("name"), Some("Jürgen"));
+        assert_eq!(*router.at(&Method::GET, "/caf%C3%A9").unwrap().value, "cafe");
+        assert_eq!(router.at(&Method::GET, "/hello/a%20b").unwrap().params.get("name"), Some("a b"));
+
+        // An encoded slash is data, not a separator
+        assert!(router.at(&Method::GET, "/hello/a%2Fb").is_none());
+        let m = router.at(&Method::GET, "/files/a%2Fb/c%20d").unwrap();
+        assert_eq!(m.params.get("path"), Some("a/b/c d"));
+
+        assert_eq!(*router.at(&Method::GET, "//hello///ferris").unwrap().value, "hello");
+        assert_eq!(*router.at(&Method::GET, "/users//7//").unwrap().value, "user");
+        assert!(router.at(&Method::GET, "/users/7").is_none());
+
+        assert_eq!(normalize_path("/users/7"), "/users/7");
+        assert_eq!(normalize_path("//users///7//"), "/users/7/");
+    }
+
+    #[test]
+    fn test_reinsert_replaces_and_invalid_patterns() {
+        let mut router = router(&[("/users/{id}", "a")]);
+
+        assert_eq!(router.insert(Some(Method::GET), "/users/{id}", "b"), Ok(Some("a")));
+        assert_eq!(*router.at(&Method::GET, "/users/1").unwrap().value, "b");
+        assert_eq!(router.insert(Some(Method::POST), "/users/{id}", "c"), Ok(None));
+        assert!(matches!(
+            router.insert(None, "/files/{*path}/x", "d"),
+            Err(RouteError::InvalidPattern(_))
+        ));
+        assert!(matches!(
+            router.insert(None, "users", "e"),
+            Err(RouteError::InvalidPattern(_))
+        ));
+        assert_eq!(router.len(), 2);
+    }
+
+    #[test]
+    fn test_converters_restrict_matches() {
+        let mut router = router(&[
+            ("/users/{name}", "name"),
+            ("/users/{id:int}", "id"),
+            ("/docs/{lang:any(en,de)}/{page:path}", "docs"),
+            ("/rates/{code:regex([A-Z]{3})}", "rate"),
+        ]);
+        router.register_converter("even", |s: &str| s.parse::<u32>().map(|n| n % 2 == 0).unwrap_or(false));
+        router.insert(Some(Method::GET), "/even/{n:even}", "even").unwrap();
+
+        // The constrained param wins even though it was registered later
+        assert_eq!(*router.at(&Method::GET, "/users/42").unwrap().value, "id");
+        assert_eq!(*router.at(&Method::GET, "/users/alice").unwrap().value, "name");
+
+        let m = router.at(&Method::GET, "/docs/de/guide/intro.html").unwrap();
+        assert_eq!(m.params.get("lang"), Some("de"));
+        assert_eq!(m.params.get("page"), Some("guide/intro.html"));
+        assert!(router.at(&Method::GET, "/docs/fr/guide").is_none());
+
+        assert!(router.at(&Method::GET, "/rates/EUR").is_some());
+        assert!(router.at(&Method::GET, "/rates/eur").is_none());
+
+        assert!(router.at(&Method::GET, "/even/4").is_some());
+        assert!(router.at(&Method::GET, "/even/5").is_none());
+
+        assert!(matches!(
+            router.insert(None, "/x/{id:nope}", "x"),
+            Err(RouteError::InvalidPattern(_))
+        ));
+        // Converters only ever see one segment
+        assert!(matches!(
+            router.insert(None, r"/v/{version:regex(\d+/\d+)}", "v"),
+            Err(RouteError::InvalidPattern(e)) if e.contains("only matches across '/'")
+        ));
+        router.insert(None, "/files/{name:regex([^/]+)}/raw", "raw").unwrap();
+        assert_eq!(*router.at(&Method::GET, "/files/a.txt/raw").unwrap().value, "raw");
+        assert!(router.at(&Method::GET, "/files/a/b/raw").is_none());
+    }
+
+    #[test]
+    fn test_lookup_reports_allowed_methods() {
+        let mut router = router(&[("/items/{id}", "get")]);
+        router.insert(Some(Method::DELETE), "/items/{id}", "delete").unwrap();
+        router.insert(Some(Method::POST), "/items/{id:int}", "post").unwrap();
+
+        assert!(matches!(router.lookup(&Method::DELETE, "/items/x"), Lookup::Found(_)));
+        assert!(matches!(router.lookup(&Method::GET, "/nothing"), Lookup::NotFound));
+
+        // HEAD is served by the GET handler
+        match router.lookup(&Method::HEAD, "/items/x") {
+            Lookup::Found(m) => assert_eq!(*m.value, "get"),
+            _ => panic!("HEAD should fall back to GET"),
+        }
+
+        // Methods are gathered from every matching pattern
+        match router.lookup(&Method::PUT, "/items/7") {
+            Lookup::MethodNotAllowed(methods) => {
+                assert_eq!(methods, vec![Method::DELETE, Method::GET, Method::HEAD, Method::POST])
+            }
+            _ => panic!("expected 405"),
+        }
+        match router.lookup(&Method::PUT, "/items/x") {
+            Lookup::MethodNotAllowed(methods) => {
+                assert_eq!(methods, vec![Method::DELETE, Method::GET, Method::HEAD])
+            }
+            _ => panic!("expected 405"),
+        }
+    }
+
+    #[test]
+    fn test_url_for() {
+        let mut router = router(&[("/users/{id:int}/posts/{post_id}", "posts"), ("/files/{*path}", "files")]);
+        router.name("user_posts", "/users/{id:int}/posts/{post_id}").unwrap();
+        router.name("files", "/files/{*path}").unwrap();
+
+        let values = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+
+        assert_eq!(
+            router.url_for("user_posts", &values(&[("id", "123"), ("post_id", "hello world")])),
+            Ok("/users/123/posts/hello%20world".to_string())
+        );
+        assert_eq!(
+            router.url_for("user_posts", &values(&[("id", "123"), ("post_id", "a/b")])),
+            Err(UrlBuildError::InvalidValue {
+                param: "post_id".to_string(),
+                value: "a/b".to_string()
+            })
+        );
+        assert_eq!(
+            router.url_for("user_posts", &values(&[("id", "1"), ("post_id", "Jürgen"), ("page", "2"), ("q", "a b")])),
+            Ok("/users/1/posts/J%C3%BCrgen?page=2&q=a+b".to_string())
+        );
+        assert_eq!(
+            router.url_for("files", &values(&[("path", "css/site main.css")])),
+            Ok("/files/css/site%20main.css".to_string())
+        );
+        assert_eq!(
+            router.url_for("user_posts", &values(&[("id", "1")])),
+            Err(UrlBuildError::MissingParam {
+                endpoint: "user_posts".to_string(),
+                param: "post_id".to_string()
+            })
+        );
+        assert!(matches!(
+            router.url_for("user_posts", &values(&[("id", "x"), ("post_id", "1")])),
+            Err(UrlBuildError::InvalidValue { .. })
+        ));
+        assert_eq!(
+            router.url_for("nope", &[]),
+            Err(UrlBuildError::UnknownEndpoint("nope".to_string()))
+        );
+    }
+}
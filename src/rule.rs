@@ -0,0 +1,116 @@
+// A URL rule plus its per-route options. Every registration helper accepts
+// `impl Into<Rule>`, so a plain path string works wherever a configured rule
+// does: `app.get(Rule::new("/users/{id}").endpoint("user"), handler)`.
+use std::fmt;
+
+use hyper::{Body, Request, Response};
+use tower::{BoxError, Layer, Service};
+
+use crate::middleware::{self, BoxedService, Middleware, RouteWrap};
+
+#[derive(Clone)]
+pub struct Rule {
+    pub(crate) path: String,
+    pub(crate) endpoint: Option<String>,
+    pub(crate) wraps: Vec<RouteWrap>,
+    // Some(None) lifts the app's limit for this route
+    pub(crate) max_content_length: Option<Option<u64>>,
+    pub(crate) strict_slashes: Option<bool>,
+}
+
+impl Rule {
+    pub fn new(path: &str) -> Self {
+        Rule {
+            path: path.to_string(),
+            endpoint: None,
+            wraps: Vec::new(),
+            max_content_length: None,
+            strict_slashes: None,
+        }
+    }
+
+    // Names the route for `url_for`. Inside a blueprint the name is prefixed
+    // with the blueprint's dotted name, e.g. "api.users.show".
+    pub fn endpoint(mut self, name: &str) -> Self {
+        self.endpoint = Some(name.to_string());
+        self
+    }
+
+    // Middleware and layers for this route only. They run inside the app's
+    // middleware and hooks, around the handler, first registered first.
+    pub fn middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        self.wraps.push(middleware::route_middleware(middleware));
+        self
+    }
+
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxedService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+    {
+        self.wraps.push(middleware::route_layer(middleware::erase_layer(layer)));
+        self
+    }
+
+    // Overrides `FlaskConfig::max_content_length` for this route, e.g. to
+    // allow large uploads on one endpoint only. App middleware still sees the
+    // app's limit, since it runs before the request is routed.
+    pub fn max_content_length(mut self, limit: u64) -> Self {
+        self.max_content_length = Some(Some(limit));
+        self
+    }
+
+    // Accepts bodies of any size on this route despite the app's limit
+    pub fn unlimited_body(mut self) -> Self {
+        self.max_content_length = Some(None);
+        self
+    }
+
+    // Overrides `FlaskConfig::strict_slashes` for this route
+    pub fn strict_slashes(mut self, strict: bool) -> Self {
+        self.strict_slashes = Some(strict);
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl fmt::Debug for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rule")
+            .field("path", &self.path)
+            .field("endpoint", &self.endpoint)
+            .field("wraps", &self.wraps.len())
+            .field("max_content_length", &self.max_content_length)
+            .field("strict_slashes", &self.strict_slashes)
+            .finish()
+    }
+}
+
+impl From<&str> for Rule {
+    fn from(path: &str) -> Self {
+        Rule::new(path)
+    }
+}
+
+impl From<String> for Rule {
+    fn from(path: String) -> Self {
+        Rule {
+            path,
+            endpoint: None,
+            wraps: Vec::new(),
+            max_content_length: None,
+            strict_slashes: None,
+        }
+    }
+}
+
+impl From<&String> for Rule {
+    fn from(path: &String) -> Self {
+        Rule::new(path)
+    }
+}
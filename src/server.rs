@@ -0,0 +1,119 @@
+// Binding the listener `FlaskApp::run_with_config` serves on
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+
+#[derive(Debug)]
+pub enum ServeError {
+    // The host could not be resolved to any address
+    Resolve { host: String, source: io::Error },
+    // No resolved address could be bound; `addr` is the last one tried
+    Bind { addr: SocketAddr, source: io::Error },
+    Listener(io::Error),
+    Server(hyper::Error),
+}
+
+impl fmt::Display for ServeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServeError::Resolve { host, source } => write!(f, "Could not resolve host {:?}: {}", host, source),
+            ServeError::Bind { addr, source } => write!(f, "Could not bind to {}: {}", addr, source),
+            ServeError::Listener(e) => write!(f, "Unusable listener: {}", e),
+            ServeError::Server(e) => write!(f, "Server error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServeError::Resolve { source, .. } | ServeError::Bind { source, .. } => Some(source),
+            ServeError::Listener(e) => Some(e),
+            ServeError::Server(e) => Some(e),
+        }
+    }
+}
+
+// Binds `host`, an IPv4 or IPv6 address ("[::1]" or "::1") or a hostname,
+// trying each address it resolves to in turn
+pub(crate) async fn bind(host: &str, port: u16) -> Result<TcpListener, ServeError> {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    let resolve_error = |source| ServeError::Resolve {
+        host: host.to_string(),
+        source,
+    };
+
+    let mut last_error = None;
+    for addr in tokio::net::lookup_host((host, port)).await.map_err(resolve_error)? {
+        match TcpListener::bind(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(source) => last_error = Some(ServeError::Bind { addr, source }),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| resolve_error(io::Error::new(io::ErrorKind::NotFound, "no addresses found"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlaskApp;
+
+    // Some CI hosts have no IPv6 loopback
+    fn has_ipv6() -> bool {
+        TcpListener::bind("[::1]:0").is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_bind_addresses() {
+        let listener = bind("127.0.0.1", 0).await.unwrap();
+        assert!(listener.local_addr().unwrap().is_ipv4());
+        if has_ipv6() {
+            let listener = bind("[::1]", 0).await.unwrap();
+            assert!(listener.local_addr().unwrap().is_ipv6());
+            let listener = bind("::1", 0).await.unwrap();
+            assert!(listener.local_addr().unwrap().is_ipv6());
+        }
+
+        let taken = bind("127.0.0.1", 0).await.unwrap().local_addr().unwrap();
+        let _held = TcpListener::bind(taken).unwrap();
+        let error = bind("127.0.0.1", taken.port()).await.unwrap_err();
+        assert!(matches!(error, ServeError::Bind { addr, .. } if addr == taken), "{}", error);
+
+        // Rejected before any resolver is asked
+        let error = bind("local\0host", 80).await.unwrap_err();
+        assert!(matches!(error, ServeError::Resolve { .. }), "{}", error);
+    }
+
+    // Asks the system resolver, which some sandboxes lack
+    #[tokio::test]
+    #[ignore]
+    async fn test_bind_a_hostname() {
+        let listener = bind("localhost", 0).await.unwrap();
+        assert!(listener.local_addr().unwrap().ip().is_loopback());
+    }
+
+    #[tokio::test]
+    async fn test_serve_on_a_listener() {
+        let app = FlaskApp::new();
+        app.get("/", |_req, _params| "served").await;
+
+        let mut hosts = vec!["127.0.0.1"];
+        if has_ipv6() {
+            hosts.push("[::1]");
+        }
+        for host in hosts {
+            let listener = bind(host, 0).await.unwrap();
+            let uri: hyper::Uri = format!("http://{}/", listener.local_addr().unwrap()).parse().unwrap();
+            tokio::select! {
+                result = app.serve(listener) => panic!("server stopped: {:?}", result),
+                response = hyper::Client::new().get(uri) => {
+                    let body = hyper::body::to_bytes(response.unwrap().into_body()).await.unwrap();
+                    assert_eq!(&body[..], b"served");
+                }
+            }
+        }
+    }
+}
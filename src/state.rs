@@ -0,0 +1,120 @@
+// Application state shared by every handler, Flask's `current_app` extensions.
+//
+// Values are registered once per type with `FlaskApp::with_state` and reached
+// either through the `State<S>` extractor or `req.state::<S>()`:
+//
+//   let app = FlaskApp::new().with_state(Pool::connect(url));
+//   app.get("/users", handler(|State(pool): State<Pool>| async move { ... })).await;
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use hyper::http::request::Parts;
+use hyper::StatusCode;
+
+use crate::extract::{FromRequestParts, Rejection};
+
+// Every registered value keyed by its type; each request carries a handle to
+// it in its extensions
+#[derive(Default)]
+pub(crate) struct StateMap {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl StateMap {
+    pub(crate) fn insert<S: Send + Sync + 'static>(&mut self, value: S) {
+        self.values.insert(TypeId::of::<S>(), Arc::new(value));
+    }
+
+    pub(crate) fn get<S: Send + Sync + 'static>(&self) -> Option<Arc<S>> {
+        let value = Arc::clone(self.values.get(&TypeId::of::<S>())?);
+        value.downcast::<S>().ok()
+    }
+}
+
+pub struct State<S>(pub Arc<S>);
+
+impl<S> Clone for State<S> {
+    fn clone(&self) -> Self {
+        State(Arc::clone(&self.0))
+    }
+}
+
+impl<S> Deref for State<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for State<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("State").field(&self.0).finish()
+    }
+}
+
+// A missing state is a programming error, hence 500 rather than 400
+impl<S: Send + Sync + 'static> FromRequestParts for State<S> {
+    type Rejection = Rejection;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Arc<StateMap>>()
+            .and_then(|states| states.get::<S>())
+            .map(State)
+            .ok_or_else(|| {
+                Rejection::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("No state of type {} was registered with FlaskApp::with_state", type_name::<S>()),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Body, Request};
+    use crate::testing::get;
+    use crate::{handler, FlaskApp, RequestExt};
+
+    #[test]
+    fn test_state_map_is_keyed_by_type() {
+        let mut states = StateMap::default();
+        states.insert(42u32);
+        states.insert(String::from("db"));
+        states.insert(7u32);
+
+        assert_eq!(states.get::<u32>().as_deref(), Some(&7));
+        assert_eq!(states.get::<String>().as_deref().map(String::as_str), Some("db"));
+        assert!(states.get::<u64>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shared_state() {
+        struct Counter(std::sync::atomic::AtomicUsize);
+
+        let app = FlaskApp::new()
+            .with_state(Counter(std::sync::atomic::AtomicUsize::new(0)))
+            .with_state(String::from("v1"));
+        app.get("/hits", handler(|State(counter): State<Counter>, version: State<String>| async move {
+            let hits = counter.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            format!("{} {}", *version, hits)
+        }))
+        .await;
+        app.get("/raw", |req: Request<Body>, _params| {
+            req.state::<String>().map(|v| v.to_string()).unwrap_or_default()
+        })
+        .await;
+        app.get("/missing", handler(|_: State<u64>| async { "unreachable" })).await;
+
+        get(&app, "/hits").await;
+        assert_eq!(get(&app, "/hits").await.body, "v1 2");
+        assert_eq!(get(&app, "/raw").await.body, "v1");
+        assert_eq!(get(&app, "/missing").await.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
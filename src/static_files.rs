@@ -0,0 +1,123 @@
+// Serving files from a directory, as used by blueprint static folders
+use std::path::{Component, Path, PathBuf};
+
+use futures_util::stream;
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Response, StatusCode};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::IntoResponse;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Flask's `send_from_directory`: 404 for anything missing or outside
+// `directory`. The file is streamed in chunks rather than read into memory.
+pub async fn send_from_directory(directory: impl AsRef<Path>, filename: &str) -> Response<Body> {
+    let Some(path) = safe_join(directory.as_ref(), filename) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Ok(file) = File::open(&path).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    Response::builder()
+        .header(CONTENT_TYPE, content_type(&path))
+        .header(CONTENT_LENGTH, len)
+        .body(Body::wrap_stream(chunks(file)))
+        .unwrap()
+}
+
+// Ends after the first read error, which aborts the response
+fn chunks(file: File) -> impl stream::Stream<Item = std::io::Result<Bytes>> + Send + 'static {
+    stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+// Rejects absolute paths and `..` so requests cannot escape the directory
+fn safe_join(directory: &Path, filename: &str) -> Option<PathBuf> {
+    let relative = Path::new(filename);
+    if filename.is_empty() || filename.contains('\\') {
+        return None;
+    }
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    Some(directory.join(relative))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json; charset=utf-8",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_join_rejects_traversal() {
+        let dir = Path::new("static");
+        assert_eq!(safe_join(dir, "css/site.css"), Some(PathBuf::from("static/css/site.css")));
+        assert_eq!(safe_join(dir, "../Cargo.toml"), None);
+        assert_eq!(safe_join(dir, "css/../../secret"), None);
+        assert_eq!(safe_join(dir, "/etc/passwd"), None);
+        assert_eq!(safe_join(dir, "a\\..\\b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_from_directory() {
+        let response = send_from_directory(".", "Cargo.toml").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Type"], "application/octet-stream");
+        let expected = std::fs::read("Cargo.toml").unwrap();
+        assert_eq!(response.headers()[CONTENT_LENGTH], expected.len().to_string().as_str());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, expected);
+
+        // Directories open on some platforms but are not files
+        let response = send_from_directory(".", "src").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send_from_directory("src", "missing.rs").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
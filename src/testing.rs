@@ -0,0 +1,33 @@
+// Sends requests through a whole app, middleware and layers included, for
+// the tests of every module
+use hyper::{Body, HeaderMap, Request, StatusCode};
+
+use crate::FlaskApp;
+
+// A response with its body read
+#[derive(Debug)]
+pub(crate) struct TestResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: String,
+}
+
+impl TestResponse {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.to_str().unwrap())
+    }
+}
+
+pub(crate) async fn send(app: &FlaskApp, req: Request<Body>) -> TestResponse {
+    let (parts, body) = app.handle_request(req).await.unwrap().into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap();
+    TestResponse {
+        status: parts.status,
+        headers: parts.headers,
+        body: String::from_utf8(body.to_vec()).unwrap(),
+    }
+}
+
+pub(crate) async fn get(app: &FlaskApp, uri: &str) -> TestResponse {
+    send(app, Request::builder().uri(uri).body(Body::empty()).unwrap()).await
+}